@@ -0,0 +1,320 @@
+use bindings::entity_key_registry::EntityKeyRegistry;
+use bindings::generator_registry::GeneratorRegistry;
+use bindings::mock_attestation_verifier::MockAttestationVerifier;
+use bindings::mock_token::MockToken;
+use bindings::mock_verifier::MockVerifier;
+use bindings::proof_marketplace::ProofMarketplace;
+use bindings::shared_types::Ask;
+use ethers::abi::Token;
+use ethers::prelude::*;
+use std::error::Error;
+use std::sync::Arc;
+
+pub type Client = SignerMiddleware<Provider<Http>, LocalWallet>;
+
+// EIP-1967 implementation slot, keccak256("eip1967.proxy.implementation") - 1
+const IMPLEMENTATION_SLOT: &str =
+    "360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc";
+
+const TOKEN_SUPPLY: u128 = 1_000_000_000 * 10u128.pow(18);
+const MARKET_CREATION_COST: u128 = 10u128.pow(18);
+const SLASHING_PENALTY: u128 = 10u128.pow(18);
+const GENERATOR_STAKE: u128 = 1_000 * 10u128.pow(18);
+const GENERATOR_COMPUTE: u64 = 100;
+const COMPUTE_PER_REQUEST: u64 = 10;
+const PROOF_GENERATION_COST: u128 = 10u128.pow(15);
+const PROPOSED_TIME: u64 = 100;
+const ASK_REWARD: u128 = 10u128.pow(17);
+// covers the reward and the platform fee
+const REQUESTER_FUNDS: u128 = 10u128.pow(18);
+const ASK_EXPIRY_BLOCKS: u64 = 1000;
+// markets only take asks some blocks after they were created
+const MARKET_ACTIVATION_BLOCKS: u64 = 200;
+
+/// Ask states of the ProofMarketplace's getAskState.
+pub const ASK_STATE_CREATE: u8 = 1;
+pub const ASK_STATE_ASSIGNED: u8 = 3;
+pub const ASK_STATE_COMPLETE: u8 = 4;
+
+pub fn client(
+    endpoint: &str,
+    wallet: LocalWallet,
+    chain_id: u64,
+) -> Result<Arc<Client>, Box<dyn Error>> {
+    let provider = Provider::<Http>::try_from(endpoint)?;
+    Ok(Arc::new(SignerMiddleware::new(
+        provider,
+        wallet.with_chain_id(chain_id),
+    )))
+}
+
+// Minimal proxy delegating every call to the implementation in the EIP-1967 slot. The contracts
+// are UUPS upgradeable and have to be initialized through a proxy.
+fn proxy_code(implementation: Address) -> Vec<u8> {
+    let slot = hex::decode(IMPLEMENTATION_SLOT).unwrap();
+    // copies the implementation address appended to the init code into the slot
+    let mut code = hex::decode("6020602038036000396000517f").unwrap();
+    code.extend(&slot);
+    code.extend(hex::decode("5560418060396000396000f3").unwrap());
+    // runtime: delegatecall with the calldata, bubble up the result
+    code.extend(hex::decode("3660008037600060003660007f").unwrap());
+    code.extend(&slot);
+    code.extend(hex::decode("545af43d6000803e603c573d6000fd5b3d6000f3").unwrap());
+    code.extend(ethers::abi::encode(&[Token::Address(implementation)]));
+    code
+}
+
+async fn deploy_proxy(
+    client: &Arc<Client>,
+    implementation: Address,
+) -> Result<Address, Box<dyn Error>> {
+    let tx = TransactionRequest::new().data(proxy_code(implementation));
+    let receipt = client
+        .send_transaction(tx, None)
+        .await?
+        .await?
+        .ok_or("proxy deployment dropped")?;
+    Ok(receipt.contract_address.ok_or("proxy not deployed")?)
+}
+
+// abi.encode(bytes pcr0, bytes pcr1, bytes pcr2)
+fn encode_pcrs(pcrs: &[[u8; 48]; 3]) -> Bytes {
+    ethers::abi::encode(
+        &pcrs
+            .iter()
+            .map(|pcr| Token::Bytes(pcr.to_vec()))
+            .collect::<Vec<_>>(),
+    )
+    .into()
+}
+
+async fn send<M: Middleware + 'static, D: ethers::abi::Detokenize>(
+    call: ContractCall<M, D>,
+) -> Result<TransactionReceipt, Box<dyn Error>> {
+    let pending = call.send().await.map_err(|err| {
+        err.decode_revert::<String>()
+            .unwrap_or_else(|| err.to_string())
+    })?;
+    Ok(pending.await?.ok_or("transaction dropped")?)
+}
+
+/// The Kalypso contracts deployed behind proxies with mock attestation and proof verifiers, and
+/// one market using them.
+pub struct Deployment {
+    pub admin: Arc<Client>,
+    pub payment_token: MockToken<Client>,
+    pub proof_marketplace: ProofMarketplace<Client>,
+    pub generator_registry: GeneratorRegistry<Client>,
+    pub entity_key_registry: EntityKeyRegistry<Client>,
+    pub market_id: U256,
+    // block before the first contract was deployed
+    pub start_block: U64,
+}
+
+impl Deployment {
+    pub async fn deploy(admin: Arc<Client>) -> Result<Self, Box<dyn Error>> {
+        let admin_address = admin.address();
+        let start_block = admin.get_block_number().await?;
+
+        let payment_token = MockToken::deploy(
+            Arc::clone(&admin),
+            (
+                admin_address,
+                U256::from(TOKEN_SUPPLY),
+                "Payment".to_string(),
+                "PAY".to_string(),
+            ),
+        )?
+        .send()
+        .await?;
+        let attestation_verifier = MockAttestationVerifier::deploy(Arc::clone(&admin), ())?
+            .send()
+            .await?;
+        let verifier = MockVerifier::deploy(Arc::clone(&admin), ())?.send().await?;
+
+        let implementation =
+            EntityKeyRegistry::deploy(Arc::clone(&admin), attestation_verifier.address())?
+                .send()
+                .await?;
+        let entity_key_registry = EntityKeyRegistry::new(
+            deploy_proxy(&admin, implementation.address()).await?,
+            Arc::clone(&admin),
+        );
+        let implementation = GeneratorRegistry::deploy(
+            Arc::clone(&admin),
+            (payment_token.address(), entity_key_registry.address()),
+        )?
+        .send()
+        .await?;
+        let generator_registry = GeneratorRegistry::new(
+            deploy_proxy(&admin, implementation.address()).await?,
+            Arc::clone(&admin),
+        );
+        let implementation = ProofMarketplace::deploy(
+            Arc::clone(&admin),
+            (
+                payment_token.address(),
+                U256::from(MARKET_CREATION_COST),
+                admin_address,
+                generator_registry.address(),
+                entity_key_registry.address(),
+            ),
+        )?
+        .send()
+        .await?;
+        let proof_marketplace = ProofMarketplace::new(
+            deploy_proxy(&admin, implementation.address()).await?,
+            Arc::clone(&admin),
+        );
+
+        send(entity_key_registry.initialize(admin_address, vec![])).await?;
+        send(generator_registry.initialize(admin_address, proof_marketplace.address())).await?;
+        send(proof_marketplace.initialize(admin_address)).await?;
+
+        // the marketplace and registry store the keys of markets and generators
+        let key_register_role = entity_key_registry.key_register_role().call().await?;
+        for registrar in [proof_marketplace.address(), generator_registry.address()] {
+            send(entity_key_registry.grant_role(key_register_role, registrar)).await?;
+        }
+
+        send(payment_token.approve(proof_marketplace.address(), U256::MAX)).await?;
+        let market_id = proof_marketplace.market_counter().call().await?;
+        send(proof_marketplace.create_marketplace(
+            Bytes::from_static(b"e2e"),
+            verifier.address(),
+            U256::from(SLASHING_PENALTY),
+            encode_pcrs(&[[1; 48], [2; 48], [3; 48]]),
+            encode_pcrs(&[[4; 48], [5; 48], [6; 48]]),
+        ))
+        .await?;
+        admin
+            .provider()
+            .request::<_, ()>("anvil_mine", [U64::from(MARKET_ACTIVATION_BLOCKS)])
+            .await?;
+
+        Ok(Self {
+            admin,
+            payment_token,
+            proof_marketplace,
+            generator_registry,
+            entity_key_registry,
+            market_id,
+            start_block,
+        })
+    }
+
+    /// Whitelists a matching engine image and registers `matching_engine_key` as its enclave key,
+    /// the same way the matching engine client does with a real attestation.
+    pub async fn register_matching_engine(
+        &self,
+        matching_engine_key: &LocalWallet,
+    ) -> Result<(), Box<dyn Error>> {
+        let pcrs = [[7; 48], [8; 48], [9; 48]];
+        let updater_role = self.proof_marketplace.updater_role().call().await?;
+        send(
+            self.proof_marketplace
+                .grant_role(updater_role, self.admin.address()),
+        )
+        .await?;
+        send(
+            self.proof_marketplace
+                .set_matching_engine_image(encode_pcrs(&pcrs)),
+        )
+        .await?;
+
+        let timestamp = self
+            .admin
+            .get_block(BlockNumber::Latest)
+            .await?
+            .ok_or("no latest block")?
+            .timestamp;
+        let public_key = matching_engine_key
+            .signer()
+            .verifying_key()
+            .to_encoded_point(false);
+        // abi.encode(signature, enclave key, pcr0, pcr1, pcr2, timestamp in ms), the mock
+        // attestation verifier accepts any signature
+        let mut attestation = vec![
+            Token::Bytes(vec![0; 65]),
+            Token::Bytes(public_key.as_bytes()[1..].to_vec()),
+        ];
+        attestation.extend(pcrs.iter().map(|pcr| Token::Bytes(pcr.to_vec())));
+        attestation.push(Token::Uint(timestamp * 1000));
+        let attestation = ethers::abi::encode(&attestation);
+
+        let digest = ethers::utils::keccak256(ethers::abi::encode(&[
+            Token::Bytes(attestation.clone()),
+            Token::Address(self.proof_marketplace.address()),
+        ]));
+        let signature = matching_engine_key.sign_message(H256(digest)).await?;
+        send(
+            self.proof_marketplace
+                .verify_matching_engine(attestation.into(), signature.to_vec().into()),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Funds, stakes and registers `generator` and joins it to the market.
+    pub async fn register_generator(&self, generator: Arc<Client>) -> Result<(), Box<dyn Error>> {
+        send(
+            self.payment_token
+                .transfer(generator.address(), U256::from(GENERATOR_STAKE)),
+        )
+        .await?;
+        let payment_token = MockToken::new(self.payment_token.address(), Arc::clone(&generator));
+        send(payment_token.approve(self.generator_registry.address(), U256::MAX)).await?;
+
+        let generator_registry =
+            GeneratorRegistry::new(self.generator_registry.address(), Arc::clone(&generator));
+        send(generator_registry.register(
+            generator.address(),
+            GENERATOR_COMPUTE.into(),
+            U256::from(GENERATOR_STAKE),
+            Bytes::from_static(b"e2e generator"),
+        ))
+        .await?;
+        send(generator_registry.join_marketplace(
+            self.market_id,
+            COMPUTE_PER_REQUEST.into(),
+            U256::from(PROOF_GENERATION_COST),
+            PROPOSED_TIME.into(),
+            false,
+            Bytes::new(),
+            Bytes::new(),
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// Creates an ask without private inputs and returns its id.
+    pub async fn create_ask(&self, requester: Arc<Client>) -> Result<U256, Box<dyn Error>> {
+        send(
+            self.payment_token
+                .transfer(requester.address(), U256::from(REQUESTER_FUNDS)),
+        )
+        .await?;
+        let payment_token = MockToken::new(self.payment_token.address(), Arc::clone(&requester));
+        send(payment_token.approve(self.proof_marketplace.address(), U256::MAX)).await?;
+
+        let proof_marketplace =
+            ProofMarketplace::new(self.proof_marketplace.address(), Arc::clone(&requester));
+        let ask_id = proof_marketplace.ask_counter().call().await?;
+        let block = requester.get_block_number().await?;
+        let ask = Ask {
+            market_id: self.market_id,
+            reward: U256::from(ASK_REWARD),
+            expiry: (block + ASK_EXPIRY_BLOCKS).as_u64().into(),
+            time_taken_for_proof_generation: PROPOSED_TIME.into(),
+            deadline: U256::zero(),
+            refund_address: requester.address(),
+            prover_data: Bytes::from_static(&[1, 2, 3]),
+        };
+        send(proof_marketplace.create_ask(ask, 0, Bytes::new(), Bytes::new())).await?;
+        Ok(ask_id)
+    }
+
+    pub async fn ask_state(&self, ask_id: U256) -> Result<u8, Box<dyn Error>> {
+        Ok(self.proof_marketplace.get_ask_state(ask_id).call().await?)
+    }
+}
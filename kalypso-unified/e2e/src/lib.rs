@@ -0,0 +1,158 @@
+//! End-to-end harness: a local anvil node with the Kalypso contracts deployed, the matching
+//! engine and the listener running against it, and a stub generator answering the listener.
+//!
+//! Needs `anvil` on the PATH, the `listener` and `matching_engine` binaries are built by the
+//! first test that starts them, see the README.
+
+pub mod contracts;
+pub mod process;
+pub mod stub_generator;
+
+use contracts::{Client, Deployment};
+use ethers::prelude::*;
+use ethers::utils::{Anvil, AnvilInstance};
+use process::Service;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use stub_generator::StubGenerator;
+use tempfile::TempDir;
+
+// block time once the setup is done, the matching engine waits for 10 confirmations
+const BLOCK_TIME_SECS: u64 = 1;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct Harness {
+    pub deployment: Deployment,
+    pub requester: Arc<Client>,
+    pub generator: StubGenerator,
+    // dropped before anvil so they don't log connection errors
+    services: Vec<Service>,
+    _dir: TempDir,
+    _anvil: AnvilInstance,
+}
+
+impl Harness {
+    /// Deploys the contracts, registers the matching engine and a generator, then starts the
+    /// matching engine and the listener, proving with a stub generator that answers every input.
+    pub async fn start() -> Result<Self, Box<dyn Error>> {
+        Self::with_generator(StubGenerator::start(Bytes::from_static(&[1])).await?).await
+    }
+
+    /// Same as `start`, with the listener forwarding the asks to `stub`.
+    pub async fn with_generator(stub: StubGenerator) -> Result<Self, Box<dyn Error>> {
+        // the marketplace exceeds the 24kB contract size limit without optimizations
+        let anvil = Anvil::new().arg("--disable-code-size-limit").spawn();
+        let rpc_url = anvil.endpoint();
+        let chain_id = anvil.chain_id();
+        let wallet = |index: usize| LocalWallet::from(anvil.keys()[index].clone());
+        let (admin, relayer, generator, requester, matching_engine) =
+            (wallet(0), wallet(1), wallet(2), wallet(3), wallet(4));
+
+        let deployment = Deployment::deploy(contracts::client(&rpc_url, admin, chain_id)?).await?;
+        deployment
+            .register_matching_engine(&matching_engine)
+            .await?;
+        deployment
+            .register_generator(contracts::client(&rpc_url, generator.clone(), chain_id)?)
+            .await?;
+        // blocks were mined per transaction so far, the services need the chain to move on its own
+        deployment
+            .admin
+            .provider()
+            .request::<_, ()>("evm_setIntervalMining", [BLOCK_TIME_SECS])
+            .await?;
+
+        let dir = tempfile::tempdir()?;
+        let services = vec![
+            process::start_matching_engine(
+                dir.path(),
+                &rpc_url,
+                chain_id,
+                &deployment,
+                &matching_engine,
+                &relayer,
+            )?,
+            process::start_listener(
+                dir.path(),
+                &rpc_url,
+                chain_id,
+                &deployment,
+                &generator,
+                stub.port,
+            )?,
+        ];
+
+        Ok(Self {
+            deployment,
+            requester: contracts::client(&rpc_url, requester, chain_id)?,
+            generator: stub,
+            services,
+            _dir: dir,
+            _anvil: anvil,
+        })
+    }
+
+    pub async fn create_ask(&self) -> Result<U256, Box<dyn Error>> {
+        self.deployment
+            .create_ask(Arc::clone(&self.requester))
+            .await
+    }
+
+    /// Waits until the ask is in `state`, failing if a service exits or `timeout` passes.
+    pub async fn wait_for_ask_state(
+        &mut self,
+        ask_id: U256,
+        state: u8,
+        timeout: Duration,
+    ) -> Result<(), Box<dyn Error>> {
+        let started = Instant::now();
+        loop {
+            let current = self.deployment.ask_state(ask_id).await?;
+            if current == state {
+                return Ok(());
+            }
+            self.ensure_running()?;
+            if started.elapsed() > timeout {
+                return Err(format!(
+                    "ask {} still in state {} after {:?}, expected {}",
+                    ask_id, current, timeout, state
+                )
+                .into());
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Waits until the generator was asked for `count` proofs, failing if a service exits or
+    /// `timeout` passes.
+    pub async fn wait_for_generator_requests(
+        &mut self,
+        count: usize,
+        timeout: Duration,
+    ) -> Result<(), Box<dyn Error>> {
+        let started = Instant::now();
+        while self.generator.requests() < count {
+            self.ensure_running()?;
+            if started.elapsed() > timeout {
+                return Err(format!(
+                    "generator asked for {} proofs after {:?}, expected {}",
+                    self.generator.requests(),
+                    timeout,
+                    count
+                )
+                .into());
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    /// Fails if the matching engine or the listener has exited.
+    pub fn ensure_running(&mut self) -> Result<(), Box<dyn Error>> {
+        for service in &mut self.services {
+            service.ensure_running()?;
+        }
+        Ok(())
+    }
+}
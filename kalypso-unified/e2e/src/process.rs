@@ -0,0 +1,185 @@
+use crate::contracts::Deployment;
+use ethers::signers::{LocalWallet, Signer};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::OnceLock;
+
+/// A binary of the workspace running as a child process, killed when dropped.
+pub struct Service {
+    name: &'static str,
+    child: Child,
+}
+
+impl Service {
+    fn spawn(name: &'static str, command: &mut Command) -> Result<Self, Box<dyn Error>> {
+        let child = command
+            .spawn()
+            .map_err(|err| format!("unable to start {}: {}", name, err))?;
+        Ok(Self { name, child })
+    }
+
+    /// Fails if the process has exited, e.g. because its config was rejected.
+    pub fn ensure_running(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.child.try_wait()? {
+            Some(status) => Err(format!("{} exited with {}", self.name, status).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Binaries are taken from E2E_BIN_DIR, or built once per test run
+fn binary(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    if let Ok(dir) = std::env::var("E2E_BIN_DIR") {
+        return Ok(PathBuf::from(dir).join(name));
+    }
+    static BINARIES: OnceLock<Result<HashMap<String, PathBuf>, String>> = OnceLock::new();
+    let binaries = BINARIES
+        .get_or_init(build_binaries)
+        .as_ref()
+        .map_err(|err| err.clone())?;
+    Ok(binaries
+        .get(name)
+        .ok_or(format!("cargo built no {} binary", name))?
+        .clone())
+}
+
+// Builds the listener and the matching engine and reads where cargo put their binaries
+fn build_binaries() -> Result<HashMap<String, PathBuf>, String> {
+    let cargo = std::env::var("CARGO").unwrap_or("cargo".to_string());
+    let output = Command::new(cargo)
+        .args(["build", "--message-format=json"])
+        .args(["-p", "listener", "-p", "matching_engine"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .map_err(|err| format!("unable to run cargo: {}", err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "building the services failed with {}:\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|message| {
+            let name = message["target"]["name"].as_str()?.to_string();
+            Some((name, PathBuf::from(message["executable"].as_str()?)))
+        })
+        .collect())
+}
+
+fn private_key(wallet: &LocalWallet) -> String {
+    hex::encode(wallet.signer().to_bytes())
+}
+
+/// Starts the matching engine in `dir`. It reads its config from
+/// `../matching_engine_config/matching_engine_config.json`, so it is run from `dir/matching_engine`.
+pub fn start_matching_engine(
+    dir: &Path,
+    rpc_url: &str,
+    chain_id: u64,
+    deployment: &Deployment,
+    matching_engine_key: &LocalWallet,
+    relayer_key: &LocalWallet,
+) -> Result<Service, Box<dyn Error>> {
+    let config_dir = dir.join("matching_engine_config");
+    let working_dir = dir.join("matching_engine");
+    fs::create_dir_all(&config_dir)?;
+    fs::create_dir_all(&working_dir)?;
+    let config = serde_json::json!({
+        "rpc_url": rpc_url,
+        "chain_id": chain_id.to_string(),
+        "matching_engine_key": private_key(matching_engine_key),
+        "relayer_private_key": private_key(relayer_key),
+        "proof_market_place": format!("{:?}", deployment.proof_marketplace.address()),
+        "generator_registry": format!("{:?}", deployment.generator_registry.address()),
+        "entity_registry": format!("{:?}", deployment.entity_key_registry.address()),
+        "start_block": deployment.start_block.to_string(),
+    });
+    fs::write(
+        config_dir.join("matching_engine_config.json"),
+        serde_json::to_string_pretty(&config)?,
+    )?;
+
+    Service::spawn(
+        "matching engine",
+        Command::new(binary("matching_engine")?).current_dir(working_dir),
+    )
+}
+
+/// Starts the listener for `generator`, proving the deployment's market with the generator at
+/// `generator_port`.
+pub fn start_listener(
+    dir: &Path,
+    rpc_url: &str,
+    chain_id: u64,
+    deployment: &Deployment,
+    generator: &LocalWallet,
+    generator_port: u16,
+) -> Result<Service, Box<dyn Error>> {
+    let config_dir = dir.join("generator_config");
+    fs::create_dir_all(&config_dir)?;
+    let market_id = deployment.market_id.to_string();
+
+    let generator_config = serde_json::json!({
+        "generator_config": [{
+            "address": format!("{:?}", generator.address()),
+            "ecies_private_key": hex::encode([0x11; 32]),
+            "supported_markets": [market_id],
+        }]
+    });
+    let runtime_config = serde_json::json!({
+        "runtime_config": {
+            "http_url": rpc_url,
+            "private_key": private_key(generator),
+            "proof_market_place": format!("{:?}", deployment.proof_marketplace.address()),
+            "generator_registry": format!("{:?}", deployment.generator_registry.address()),
+            "entity_registry": format!("{:?}", deployment.entity_key_registry.address()),
+            "start_block": deployment.start_block.as_u64(),
+            "chain_id": chain_id,
+            "params_path": dir.join("params"),
+            "journal_path": dir.join("listener_journal"),
+            "ingestion_mode": "polling",
+            "confirmations": 1,
+            "config_reload_secs": 0,
+            "markets": {
+                market_id: {
+                    "port": generator_port.to_string(),
+                    // no IVS runs, asks the generator refuses never get their inputs signed
+                    "ivs_url": "http://127.0.0.1:1",
+                }
+            },
+        }
+    });
+    let generator_config_path = config_dir.join("generator_config.json");
+    let runtime_config_path = config_dir.join("runtime_config.json");
+    fs::write(
+        &generator_config_path,
+        serde_json::to_string_pretty(&generator_config)?,
+    )?;
+    fs::write(
+        &runtime_config_path,
+        serde_json::to_string_pretty(&runtime_config)?,
+    )?;
+
+    Service::spawn(
+        "listener",
+        Command::new(binary("listener")?)
+            .arg("--generator-config")
+            .arg(generator_config_path)
+            .arg("--runtime-config")
+            .arg(runtime_config_path)
+            .current_dir(dir),
+    )
+}
@@ -0,0 +1,117 @@
+use ethers::types::Bytes;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Answers the listener's `/api/generateProof` requests with a fixed proof, or refuses them, in
+/// place of a real generator. The mock verifier accepts any proof.
+pub struct StubGenerator {
+    pub port: u16,
+    requests: Arc<AtomicUsize>,
+    server: JoinHandle<()>,
+}
+
+impl StubGenerator {
+    pub async fn start(proof: Bytes) -> io::Result<Self> {
+        let body = serde_json::json!({ "message": "stub proof", "data": proof });
+        Self::answering("200 OK", body.to_string()).await
+    }
+
+    /// Refuses every input the way a generator does without a signing key: a 4xx without data.
+    pub async fn rejecting(message: &str) -> io::Result<Self> {
+        let body = serde_json::json!({ "message": message, "data": null });
+        Self::answering("400 Bad Request", body.to_string()).await
+    }
+
+    async fn answering(status: &'static str, body: String) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&requests);
+        let server = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let counter = Arc::clone(&counter);
+                let body = body.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = serve(stream, status, &body, &counter).await {
+                        log::warn!("Stub generator request failed: {}", err);
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            port,
+            requests,
+            server,
+        })
+    }
+
+    /// Proof requests served so far.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for StubGenerator {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+// Reads one request and replies to proof requests with the answer, or an empty 200 to health
+// checks
+async fn serve(
+    mut stream: TcpStream,
+    status: &str,
+    body: &str,
+    requests: &AtomicUsize,
+) -> io::Result<()> {
+    let mut request = vec![];
+    let mut buffer = [0; 4096];
+    let header_end = loop {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&request[..header_end]).to_lowercase();
+    let content_length = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|length| length.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    while request.len() < header_end + content_length {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let response = if head.starts_with("post /api/generateproof") {
+        requests.fetch_add(1, Ordering::SeqCst);
+        format!(
+            "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,55 @@
+use e2e::contracts::{ASK_STATE_ASSIGNED, ASK_STATE_COMPLETE, ASK_STATE_CREATE};
+use e2e::stub_generator::StubGenerator;
+use e2e::Harness;
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs anvil"]
+async fn ask_is_assigned_and_completed() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let mut harness = Harness::start().await.unwrap();
+
+    let ask_id = harness.create_ask().await.unwrap();
+    assert_eq!(
+        harness.deployment.ask_state(ask_id).await.unwrap(),
+        ASK_STATE_CREATE
+    );
+
+    // the matching engine waits for 10 confirmations before it relays the assignment
+    harness
+        .wait_for_ask_state(ask_id, ASK_STATE_ASSIGNED, Duration::from_secs(120))
+        .await
+        .unwrap();
+    harness
+        .wait_for_ask_state(ask_id, ASK_STATE_COMPLETE, Duration::from_secs(120))
+        .await
+        .unwrap();
+    assert_eq!(harness.generator.requests(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs anvil"]
+async fn rejected_ask_is_not_completed() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let stub = StubGenerator::rejecting("invalid inputs").await.unwrap();
+    let mut harness = Harness::with_generator(stub).await.unwrap();
+
+    let ask_id = harness.create_ask().await.unwrap();
+    harness
+        .wait_for_ask_state(ask_id, ASK_STATE_ASSIGNED, Duration::from_secs(120))
+        .await
+        .unwrap();
+    // without an IVS to sign the refused inputs the listener retries the ask, 5 then 10 seconds
+    // apart, and abandons it after its third attempt
+    harness
+        .wait_for_generator_requests(3, Duration::from_secs(60))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_secs(20)).await;
+    harness.ensure_running().unwrap();
+    assert_eq!(harness.generator.requests(), 3);
+    assert_eq!(
+        harness.deployment.ask_state(ask_id).await.unwrap(),
+        ASK_STATE_ASSIGNED
+    );
+}
@@ -22,14 +22,12 @@ pub struct SecretData {
 
 #[allow(unused)]
 pub fn decrypt_ecies(receiver_priv: &[u8], msg: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
-    let result = decrypt(receiver_priv, msg).unwrap();
-    Ok(result)
+    Ok(decrypt(receiver_priv, msg)?)
 }
 
 #[allow(unused)]
 pub fn encrypt_ecies(receiver_pub: &[u8], msg: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
-    let result = encrypt(receiver_pub, msg).unwrap();
-    Ok(result)
+    Ok(encrypt(receiver_pub, msg)?)
 }
 
 pub fn decrypt_aes(encrypted_data: &[u8], secret_key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
@@ -171,7 +169,7 @@ pub fn decrypt_data_with_ecies_and_aes(
     let decrypted_secret_key = decrypt(private_key, acl_data);
     match decrypted_secret_key {
         Ok(secret_key) => {
-            let decrypted_data = try_decrypt(encrypted_data, &secret_key, market_id).unwrap();
+            let decrypted_data = try_decrypt(encrypted_data, &secret_key, market_id)?;
 
             Ok(decrypted_data)
         }
@@ -0,0 +1,283 @@
+use crate::generator_store::Generator;
+use crate::metrics;
+use bindings::proof_marketplace as pmp;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// What happens to assigned asks that break a generator's acceptance policy.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AcceptanceMode {
+    // log and count the violation but prove the ask, an assigned ask that isn't proven gets the
+    // generator slashed
+    #[default]
+    Flag,
+    Skip,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct AcceptanceConfig {
+    pub mode: AcceptanceMode,
+}
+
+/// Acceptance rules of a market, under `acceptance` in its entry of `markets`. Unset rules
+/// aren't checked.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct MarketRules {
+    // longest proof generation time (in blocks) an ask may ask for
+    pub max_proving_time: Option<u64>,
+    // lowest reward taken in the market, on top of the generators' own min_reward
+    pub min_reward: Option<U256>,
+}
+
+/// An assigned ask as seen by the acceptance rules.
+pub struct AskCandidate<'a> {
+    pub ask_id: U256,
+    pub ask: &'a pmp::Ask,
+    pub generator: &'a Generator,
+    pub market: &'a MarketRules,
+    // slashing penalty of the ask's market, locked from the generator's stake while it is proven
+    pub stake_required: U256,
+    // stake locked by the generator's other asks in progress
+    pub locked_stake: U256,
+}
+
+/// A check an assigned ask has to pass before the generator proves it. The policy runs the
+/// built-in rules below, none are loaded from the config.
+pub trait AcceptanceRule: Send + Sync {
+    // used in logs and as metric label
+    fn name(&self) -> &'static str;
+
+    fn check(&self, candidate: &AskCandidate) -> Result<(), String>;
+}
+
+struct MinReward;
+
+impl AcceptanceRule for MinReward {
+    fn name(&self) -> &'static str {
+        "min_reward"
+    }
+
+    fn check(&self, candidate: &AskCandidate) -> Result<(), String> {
+        let min_reward = candidate
+            .generator
+            .min_reward
+            .max(candidate.market.min_reward);
+        match min_reward {
+            Some(min_reward) if candidate.ask.reward < min_reward => Err(format!(
+                "reward {} is below the minimum of {}",
+                candidate.ask.reward, min_reward
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct StakeLimit;
+
+impl AcceptanceRule for StakeLimit {
+    fn name(&self) -> &'static str {
+        "staked_amount"
+    }
+
+    fn check(&self, candidate: &AskCandidate) -> Result<(), String> {
+        let Some(staked_amount) = candidate.generator.staked_amount else {
+            return Ok(());
+        };
+        let locked = candidate
+            .locked_stake
+            .saturating_add(candidate.stake_required);
+        if locked > staked_amount {
+            Err(format!(
+                "{} stake would be locked, only {} is staked",
+                locked, staked_amount
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+struct MaxProvingTime;
+
+impl AcceptanceRule for MaxProvingTime {
+    fn name(&self) -> &'static str {
+        "max_proving_time"
+    }
+
+    fn check(&self, candidate: &AskCandidate) -> Result<(), String> {
+        match candidate.market.max_proving_time {
+            Some(max) if candidate.ask.time_taken_for_proof_generation > U256::from(max) => {
+                Err(format!(
+                    "proving time of {} blocks exceeds the market's maximum of {}",
+                    candidate.ask.time_taken_for_proof_generation, max
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Decides whether the generators take their assigned asks and tracks the stake locked by the
+/// asks in progress.
+pub struct AcceptancePolicy {
+    pub mode: AcceptanceMode,
+    rules: Vec<Arc<dyn AcceptanceRule>>,
+    // stake locked per ask in progress and the generator it is locked from
+    locked: Mutex<HashMap<U256, (Address, U256)>>,
+    // slashing penalties of the markets, they don't change once a market is created
+    penalties: RwLock<HashMap<U256, U256>>,
+}
+
+impl AcceptancePolicy {
+    pub fn new(config: &AcceptanceConfig) -> Self {
+        Self {
+            mode: config.mode,
+            rules: vec![],
+            locked: Mutex::new(HashMap::new()),
+            penalties: RwLock::new(HashMap::new()),
+        }
+        .with_rule(Arc::new(MinReward))
+        .with_rule(Arc::new(StakeLimit))
+        .with_rule(Arc::new(MaxProvingTime))
+    }
+
+    fn with_rule(mut self, rule: Arc<dyn AcceptanceRule>) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Stake the market locks per assigned ask, fetched once per market.
+    pub async fn stake_required<M: Middleware + 'static>(
+        &self,
+        proof_marketplace: &pmp::ProofMarketplace<M>,
+        market_id: U256,
+    ) -> Result<U256, Box<dyn Error>> {
+        if let Some(penalty) = self.penalties.read().unwrap().get(&market_id) {
+            return Ok(*penalty);
+        }
+        let (_, _, slashing_penalty, ..) = proof_marketplace.market_data(market_id).call().await?;
+        self.penalties
+            .write()
+            .unwrap()
+            .insert(market_id, slashing_penalty);
+        Ok(slashing_penalty)
+    }
+
+    pub fn locked_stake(&self, generator: &Address) -> U256 {
+        self.locked
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|(address, _)| address == generator)
+            .fold(U256::zero(), |total, (_, stake)| {
+                total.saturating_add(*stake)
+            })
+    }
+
+    pub fn lock(&self, ask_id: U256, generator: Address, stake: U256) {
+        self.locked
+            .lock()
+            .unwrap()
+            .insert(ask_id, (generator, stake));
+    }
+
+    pub fn release(&self, ask_id: &U256) {
+        if let Ok(mut locked) = self.locked.lock() {
+            locked.remove(ask_id);
+        }
+    }
+
+    /// The rules the ask breaks, by rule name. Every violation is counted.
+    pub fn violations(&self, candidate: &AskCandidate) -> Vec<(&'static str, String)> {
+        let mut violations = vec![];
+        for rule in &self.rules {
+            if let Err(reason) = rule.check(candidate) {
+                metrics::POLICY_VIOLATIONS
+                    .with_label_values(&[rule.name()])
+                    .inc();
+                violations.push((rule.name(), reason));
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ecies::{PublicKey, SecretKey};
+
+    fn broken_rules(
+        policy: &AcceptancePolicy,
+        generator: &Generator,
+        market: &MarketRules,
+        ask: &pmp::Ask,
+    ) -> Vec<&'static str> {
+        policy
+            .violations(&AskCandidate {
+                ask_id: U256::one(),
+                ask,
+                generator,
+                market,
+                stake_required: U256::from(400),
+                locked_stake: policy.locked_stake(&generator.address),
+            })
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn applies_generator_and_market_rules() {
+        let ecies_priv_key = SecretKey::parse(&[1; 32]).unwrap();
+        let generator = Generator {
+            address: Address::from_low_u64_be(1),
+            supported_market_ids: vec![U256::from(3)],
+            ecies_priv_key,
+            ecies_pub_key: PublicKey::from_secret_key(&ecies_priv_key),
+            min_reward: Some(U256::from(100)),
+            staked_amount: Some(U256::from(1000)),
+        };
+        let policy = AcceptancePolicy::new(&AcceptanceConfig {
+            mode: AcceptanceMode::Skip,
+        });
+        let market: MarketRules = serde_json::from_str(r#"{"max_proving_time": 50}"#).unwrap();
+        let ask = |reward: u64, time: u64| pmp::Ask {
+            market_id: U256::from(3),
+            reward: U256::from(reward),
+            time_taken_for_proof_generation: U256::from(time),
+            ..Default::default()
+        };
+        let good = ask(100, 50);
+        assert!(broken_rules(&policy, &generator, &market, &good).is_empty());
+        assert_eq!(
+            broken_rules(&policy, &generator, &market, &ask(99, 51)),
+            vec!["min_reward", "max_proving_time"]
+        );
+        // markets without rules only apply the generator's
+        let unlimited = MarketRules::default();
+        assert!(broken_rules(&policy, &generator, &unlimited, &ask(100, 500)).is_empty());
+        let pricier: MarketRules = serde_json::from_str(r#"{"min_reward": "0x96"}"#).unwrap();
+        assert_eq!(
+            broken_rules(&policy, &generator, &pricier, &good),
+            vec!["min_reward"]
+        );
+
+        // each ask in progress locks 400 of the 1000 staked
+        policy.lock(U256::from(10), generator.address, U256::from(400));
+        assert!(broken_rules(&policy, &generator, &market, &good).is_empty());
+        policy.lock(U256::from(11), generator.address, U256::from(400));
+        assert_eq!(
+            broken_rules(&policy, &generator, &market, &good),
+            vec!["staked_amount"]
+        );
+        policy.release(&U256::from(11));
+        assert!(broken_rules(&policy, &generator, &market, &good).is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::error::PipelineError;
+use crate::journal::Journal;
+use crate::lifecycle;
+use crate::metrics;
+use bindings::proof_marketplace::{AskCreatedFilter, ProofMarketplace, TaskCreatedFilter};
+use ethers::prelude::*;
+use std::error::Error;
+use std::time::Duration;
+
+// eth_getLogs is limited to 10000 blocks by most providers
+const LOOKUP_CHUNK: u64 = 9999;
+const LOOKUP_CHUNK_DELAY: Duration = Duration::from_millis(250);
+
+/// AskCreated and TaskCreated logs of the proof marketplace, scanned together so every ask is
+/// indexed before it can be assigned, and the logs that close asks.
+pub fn marketplace_filter<M: Middleware>(contract: &ProofMarketplace<M>) -> Filter {
+    let mut topics = vec![
+        AskCreatedFilter::signature(),
+        TaskCreatedFilter::signature(),
+    ];
+    topics.extend(lifecycle::topics());
+    Filter::new().address(contract.address()).topic0(topics)
+}
+
+pub fn is_ask_created(log: &Log) -> bool {
+    log.topics.first() == Some(&AskCreatedFilter::signature())
+}
+
+/// Adds an AskCreated log to the cache, or drops it again if the log was reorged out.
+pub fn index_ask_created<M: Middleware>(
+    journal: &Journal,
+    contract: &ProofMarketplace<M>,
+    log: Log,
+) -> Result<(), Box<dyn Error>> {
+    let event = contract.decode_event::<AskCreatedFilter>("AskCreated", log.topics, log.data)?;
+    if log.removed == Some(true) {
+        journal.remove_cached_ask_created(event.ask_id)?;
+    } else {
+        journal.cache_ask_created(&event, log.block_number)?;
+    }
+    Ok(())
+}
+
+/// The AskCreated event of `ask_id`, from the cache or else searched backwards from
+/// `task_block` (where the ask was assigned) down to `start_block`.
+pub async fn find_ask_created<M: Middleware + 'static>(
+    contract: &ProofMarketplace<M>,
+    journal: &Journal,
+    ask_id: U256,
+    start_block: U64,
+    task_block: U64,
+) -> Result<AskCreatedFilter, PipelineError> {
+    match journal.cached_ask_created(ask_id) {
+        Ok(Some(event)) => {
+            metrics::ASK_LOOKUPS.with_label_values(&["cache"]).inc();
+            return Ok(event);
+        }
+        Ok(None) => {}
+        Err(err) => log::error!("Failed to read the AskCreated cache: {}", err),
+    }
+    metrics::ASK_LOOKUPS.with_label_values(&["rpc"]).inc();
+    log::info!(
+        "AskCreated of ask {} is not cached, searching blocks {} to {}",
+        ask_id,
+        start_block,
+        task_block
+    );
+
+    let mut end_block = task_block;
+    while start_block <= end_block {
+        let begin = end_block
+            .saturating_sub(LOOKUP_CHUNK.into())
+            .max(start_block);
+        let filter = contract
+            .ask_created_filter()
+            .filter
+            .from_block(begin)
+            .to_block(end_block)
+            .topic1(ask_id);
+        let logs = contract
+            .client()
+            .get_logs(&filter)
+            .await
+            .map_err(|err| PipelineError::RpcError(err.to_string()))?;
+        if let Some(log) = logs.into_iter().next_back() {
+            let event = contract
+                .decode_event::<AskCreatedFilter>("AskCreated", log.topics, log.data)
+                .map_err(|err| PipelineError::RpcError(err.to_string()))?;
+            if let Err(err) = journal.cache_ask_created(&event, log.block_number) {
+                log::error!("Failed to cache AskCreated of ask {}: {}", ask_id, err);
+            }
+            return Ok(event);
+        }
+        if begin == start_block {
+            break;
+        }
+        end_block = begin - 1;
+        tokio::time::sleep(LOOKUP_CHUNK_DELAY).await;
+    }
+    Err(PipelineError::AskNotFound(ask_id))
+}
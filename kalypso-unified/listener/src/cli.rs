@@ -0,0 +1,46 @@
+use clap::{Parser, Subcommand};
+use ethers::types::U256;
+
+/// Listens for asks assigned to the configured generators, proves them and submits the proofs.
+#[derive(Parser, Debug)]
+#[command(name = "listener", version)]
+pub struct Cli {
+    /// Path of generator_config.json, defaults to ./generator_config or ../generator_config
+    #[arg(long, env = "LISTENER_GENERATOR_CONFIG", global = true)]
+    pub generator_config: Option<String>,
+
+    /// Path of runtime_config.json, defaults to ./generator_config or ../generator_config
+    #[arg(long, env = "LISTENER_RUNTIME_CONFIG", global = true)]
+    pub runtime_config: Option<String>,
+
+    /// Fetch and decrypt inputs and call the generator, but never submit anything on-chain.
+    /// The journal is kept in a temporary database so the real one is left untouched.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Re-drive a single (possibly historical) ask end-to-end and exit
+    Replay {
+        /// Id of the ask to replay
+        #[arg(long, value_parser = parse_u256)]
+        ask_id: U256,
+        /// First block to search for the ask's events, defaults to start_block of the runtime config
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Chain of the ask, needed when several chains are configured
+        #[arg(long)]
+        chain_id: Option<u64>,
+    },
+    /// Check the configured generators against the GeneratorRegistry and EntityKeyRegistry,
+    /// print the report as JSON and exit
+    Check,
+}
+
+fn parse_u256(value: &str) -> Result<U256, String> {
+    U256::from_dec_str(value).map_err(|err| err.to_string())
+}
@@ -0,0 +1,132 @@
+use crate::processor::{Markets, Shared};
+use crate::{
+    apply_env_overrides, config_path, generator_backend, load_generator, market_concurrency,
+    read_config, Config, RuntimeConfig,
+};
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+
+const GENERATOR_CONFIG: &str = "generator_config.json";
+const RUNTIME_CONFIG: &str = "runtime_config.json";
+
+/// Reloads the generators and markets whenever one of the config files changes, checked every
+/// `interval` (never if it is zero), or when the listener receives SIGHUP.
+pub async fn watch(
+    shared: Arc<Shared>,
+    generator_config: Option<String>,
+    runtime_config: Option<String>,
+    interval: Duration,
+) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(err) => {
+            log::warn!(
+                "Unable to listen for SIGHUP, config reloads only on change: {}",
+                err
+            );
+            None
+        }
+    };
+    let paths = [
+        config_path(generator_config.as_deref(), GENERATOR_CONFIG),
+        config_path(runtime_config.as_deref(), RUNTIME_CONFIG),
+    ];
+    let mut last_modified = modified_times(&paths);
+
+    loop {
+        let on_change = async {
+            if interval.is_zero() {
+                std::future::pending::<()>().await
+            }
+            tokio::time::sleep(interval).await
+        };
+        let on_hangup = async {
+            match hangup.as_mut() {
+                Some(hangup) => hangup.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = on_change => {
+                let modified = modified_times(&paths);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+                log::info!("Config files changed, reloading");
+            }
+            _ = on_hangup => log::info!("Received SIGHUP, reloading config"),
+        }
+
+        match reload(
+            &shared,
+            generator_config.as_deref(),
+            runtime_config.as_deref(),
+        ) {
+            Ok(changes) if changes.is_empty() => log::info!("Config reloaded, nothing changed"),
+            Ok(changes) => log::info!("Config reloaded: {}", changes.join(", ")),
+            Err(err) => log::error!("Config reload failed, keeping the current config: {}", err),
+        }
+    }
+}
+
+fn modified_times(paths: &[String]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Applies the generators of generator_config.json and the markets of runtime_config.json.
+/// Nothing is changed unless every generator and market in the files is valid. Other runtime
+/// settings need a restart.
+pub fn reload(
+    shared: &Shared,
+    generator_config: Option<&str>,
+    runtime_config: Option<&str>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut config: Config =
+        serde_json::from_str(&read_config(generator_config, GENERATOR_CONFIG)?)?;
+    let runtime: RuntimeConfig =
+        serde_json::from_str(&read_config(runtime_config, RUNTIME_CONFIG)?)?;
+    let runtime = runtime.runtime_config;
+    apply_env_overrides(&mut config);
+
+    let mut generators = vec![];
+    let mut addresses = HashSet::new();
+    for generator_config in config.generator_config {
+        let generator = load_generator(generator_config)?;
+        if !addresses.insert(generator.address) {
+            return Err("Generator Address mentioned twice in the network".into());
+        }
+        generators.push(generator);
+    }
+
+    let current_markets = Arc::clone(&shared.markets.read().unwrap());
+    let markets_changed =
+        serde_json::to_value(&current_markets.details)? != serde_json::to_value(&runtime.markets)?;
+    let new_markets = if markets_changed {
+        let concurrency = market_concurrency(&runtime.markets)?;
+        Some((
+            Markets {
+                generators: generator_backend::build_backends(&runtime.markets)?,
+                details: runtime.markets,
+            },
+            concurrency,
+        ))
+    } else {
+        None
+    };
+
+    let mut changes = shared.key_store.write().unwrap().sync(generators);
+    if let Some((markets, concurrency)) = new_markets {
+        // running proofs hold on to the market config they started with
+        *shared.markets.write().unwrap() = Arc::new(markets);
+        shared.scheduler.set_market_concurrency(concurrency);
+        changes.push("updated markets".to_string());
+    }
+    Ok(changes)
+}
@@ -0,0 +1,305 @@
+use crate::generator_store::Generator;
+use crate::processor::AskContext;
+use bindings::entity_key_registry::EntityKeyRegistry;
+use bindings::generator_registry::GeneratorRegistry;
+use ecies::PublicKey;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What happens to generators that fail the on-chain checks.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsistencyMode {
+    // log the report and keep proving
+    #[default]
+    Warn,
+    // stop taking asks for the failing generator and market until a later check passes
+    Refuse,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ConsistencyConfig {
+    pub mode: ConsistencyMode,
+    // how often the checks are repeated after startup, 0 only checks at startup
+    pub interval_secs: u64,
+}
+
+impl Default for ConsistencyConfig {
+    fn default() -> Self {
+        Self {
+            mode: ConsistencyMode::Warn,
+            interval_secs: 600,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStatus {
+    Matches,
+    // no key in the EntityKeyRegistry, asks can't be encrypted for the generator
+    NotRegistered,
+    Mismatch,
+    // no entity_registry configured
+    Unchecked,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MarketReport {
+    pub market_id: U256,
+    pub joined: bool,
+    pub ecies_key: KeyStatus,
+}
+
+impl MarketReport {
+    fn passed(&self) -> bool {
+        self.joined
+            && self.ecies_key != KeyStatus::NotRegistered
+            && self.ecies_key != KeyStatus::Mismatch
+    }
+}
+
+/// Result of the on-chain checks of one generator.
+#[derive(Debug, Serialize, Clone)]
+pub struct GeneratorReport {
+    pub address: Address,
+    pub registered: bool,
+    pub markets: Vec<MarketReport>,
+}
+
+impl GeneratorReport {
+    pub fn issues(&self) -> Vec<String> {
+        let mut issues = vec![];
+        if !self.registered {
+            issues.push("not registered in the GeneratorRegistry".to_string());
+        }
+        for market in &self.markets {
+            if !market.joined {
+                issues.push(format!("has not joined market {}", market.market_id));
+            }
+            match market.ecies_key {
+                KeyStatus::NotRegistered => issues.push(format!(
+                    "no ECIES key registered for market {}",
+                    market.market_id
+                )),
+                KeyStatus::Mismatch => issues.push(format!(
+                    "ECIES key registered for market {} differs from the local one",
+                    market.market_id
+                )),
+                KeyStatus::Matches | KeyStatus::Unchecked => {}
+            }
+        }
+        issues
+    }
+
+    /// Markets the generator should not take asks for.
+    pub fn failed_markets(&self) -> Vec<U256> {
+        self.markets
+            .iter()
+            .filter(|market| !self.registered || !market.passed())
+            .map(|market| market.market_id)
+            .collect()
+    }
+}
+
+// The registry stores the uncompressed key without its 0x04 prefix
+fn key_status(local: &PublicKey, registered: &[u8]) -> KeyStatus {
+    if registered.len() < 2 {
+        KeyStatus::NotRegistered
+    } else if local.serialize()[1..] == *registered {
+        KeyStatus::Matches
+    } else {
+        KeyStatus::Mismatch
+    }
+}
+
+/// Checks generators against the GeneratorRegistry and, if configured, the EntityKeyRegistry.
+pub struct ConsistencyChecker<M> {
+    generator_registry: GeneratorRegistry<M>,
+    entity_key_registry: Option<EntityKeyRegistry<M>>,
+    pub config: ConsistencyConfig,
+}
+
+impl<M: Middleware + 'static> ConsistencyChecker<M> {
+    pub fn new(
+        client: Arc<M>,
+        generator_registry: Address,
+        entity_key_registry: Option<Address>,
+        config: ConsistencyConfig,
+    ) -> Self {
+        if entity_key_registry.is_none() {
+            log::warn!("No entity_registry configured, ECIES keys are not checked on-chain");
+        }
+        Self {
+            generator_registry: GeneratorRegistry::new(generator_registry, Arc::clone(&client)),
+            entity_key_registry: entity_key_registry
+                .map(|address| EntityKeyRegistry::new(address, client)),
+            config,
+        }
+    }
+
+    pub async fn check_generator(
+        &self,
+        generator: &Generator,
+    ) -> Result<GeneratorReport, Box<dyn Error>> {
+        let (reward_address, ..) = self
+            .generator_registry
+            .generator_registry(generator.address)
+            .call()
+            .await?;
+
+        let mut markets = vec![];
+        for market_id in &generator.supported_market_ids {
+            let (state, ..) = self
+                .generator_registry
+                .generator_info_per_market(generator.address, *market_id)
+                .call()
+                .await?;
+            let ecies_key = match &self.entity_key_registry {
+                Some(registry) => {
+                    let registered = registry
+                        .pub_key(generator.address, *market_id)
+                        .call()
+                        .await?;
+                    key_status(&generator.ecies_pub_key, &registered)
+                }
+                None => KeyStatus::Unchecked,
+            };
+            markets.push(MarketReport {
+                market_id: *market_id,
+                // state 0 is the registry's NULL state
+                joined: state != 0,
+                ecies_key,
+            });
+        }
+
+        Ok(GeneratorReport {
+            address: generator.address,
+            registered: reward_address != Address::zero(),
+            markets,
+        })
+    }
+
+    pub async fn check(
+        &self,
+        generators: &[Generator],
+    ) -> Result<Vec<GeneratorReport>, Box<dyn Error>> {
+        let mut reports = vec![];
+        for generator in generators {
+            reports.push(self.check_generator(generator).await?);
+        }
+        Ok(reports)
+    }
+}
+
+/// Checks the configured generators, logs the report and, in refuse mode, updates the markets
+/// the listener won't take asks for. The previous verdict stays if the chain can't be queried.
+pub async fn apply<M: Middleware + 'static>(
+    ask_context: &AskContext,
+    checker: &ConsistencyChecker<M>,
+) -> Result<Vec<GeneratorReport>, Box<dyn Error>> {
+    let generators = ask_context.shared.key_store.read().unwrap().generators();
+    let reports = checker.check(&generators).await?;
+
+    let mut refused = HashSet::new();
+    for report in &reports {
+        let issues = report.issues();
+        if issues.is_empty() {
+            log::info!("Generator {:?} passed the on-chain checks", report.address);
+            continue;
+        }
+        log::warn!(
+            "Generator {:?} failed the on-chain checks: {}. Report: {}",
+            report.address,
+            issues.join(", "),
+            serde_json::to_string(report)?
+        );
+        if checker.config.mode == ConsistencyMode::Refuse {
+            for market_id in report.failed_markets() {
+                refused.insert((report.address, market_id));
+            }
+        }
+    }
+
+    let mut current = ask_context.refused_markets.write().unwrap();
+    for (address, market_id) in refused.difference(&current) {
+        log::warn!(
+            "Refusing asks of generator {:?} in market {}",
+            address,
+            market_id
+        );
+    }
+    for (address, market_id) in current.difference(&refused) {
+        log::info!(
+            "Accepting asks of generator {:?} in market {} again",
+            address,
+            market_id
+        );
+    }
+    *current = refused;
+    Ok(reports)
+}
+
+/// Repeats the checks every `interval_secs` of the checker's config.
+pub async fn watch<M: Middleware + 'static>(
+    ask_context: Arc<AskContext>,
+    checker: Arc<ConsistencyChecker<M>>,
+) {
+    if checker.config.interval_secs == 0 {
+        return;
+    }
+    let interval = Duration::from_secs(checker.config.interval_secs);
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(err) = apply(&ask_context, &checker).await {
+            log::error!("On-chain consistency checks failed: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ecies::SecretKey;
+
+    #[test]
+    fn reports_failed_markets() {
+        let local = PublicKey::from_secret_key(&SecretKey::parse(&[1; 32]).unwrap());
+        let other = PublicKey::from_secret_key(&SecretKey::parse(&[2; 32]).unwrap());
+        assert_eq!(
+            key_status(&local, &local.serialize()[1..]),
+            KeyStatus::Matches
+        );
+        assert_eq!(
+            key_status(&local, &other.serialize()[1..]),
+            KeyStatus::Mismatch
+        );
+        assert_eq!(key_status(&local, &[]), KeyStatus::NotRegistered);
+
+        let market = |id: u64, joined: bool, ecies_key: KeyStatus| MarketReport {
+            market_id: U256::from(id),
+            joined,
+            ecies_key,
+        };
+        let mut report = GeneratorReport {
+            address: Address::from_low_u64_be(1),
+            registered: true,
+            markets: vec![
+                market(1, true, KeyStatus::Matches),
+                market(2, false, KeyStatus::Matches),
+                market(3, true, KeyStatus::Mismatch),
+                market(4, true, KeyStatus::Unchecked),
+            ],
+        };
+        assert_eq!(report.issues().len(), 2);
+        assert_eq!(report.failed_markets(), vec![U256::from(2), U256::from(3)]);
+
+        report.registered = false;
+        assert_eq!(report.failed_markets().len(), 4);
+    }
+}
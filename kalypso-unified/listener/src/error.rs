@@ -0,0 +1,90 @@
+use ethers::types::U256;
+use std::fmt;
+
+/// Why an ask could not be taken through the proof pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipelineError {
+    RpcError(String),
+    // no AskCreated event for the ask
+    AskNotFound(U256),
+    // generators and the IVS take u64 ask ids
+    AskIdOutOfRange(U256),
+    DecryptionFailed(String),
+    DecompressionFailed(String),
+    // the generator could not be reached or did not answer
+    GeneratorUnavailable(String),
+    // the generator refused the inputs without signing them
+    GeneratorRejected(String),
+    IvsFailed(String),
+    SubmissionFailed(String),
+}
+
+/// What the pipeline does with an ask after an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    // try the ask again after a short delay, abandon it once the attempts are used up
+    Retry,
+    // the inputs are at fault, get a signature over the invalid inputs from the IVS and submit it
+    SubmitInvalid,
+    Abandon,
+}
+
+impl PipelineError {
+    pub fn action(&self) -> Action {
+        match self {
+            PipelineError::RpcError(_) => Action::Retry,
+            PipelineError::AskNotFound(_) => Action::Abandon,
+            PipelineError::AskIdOutOfRange(_) => Action::Abandon,
+            PipelineError::DecryptionFailed(_) => Action::SubmitInvalid,
+            PipelineError::DecompressionFailed(_) => Action::SubmitInvalid,
+            PipelineError::GeneratorUnavailable(_) => Action::Retry,
+            PipelineError::GeneratorRejected(_) => Action::SubmitInvalid,
+            PipelineError::IvsFailed(_) => Action::Retry,
+            // the submitter already retried and bumped fees
+            PipelineError::SubmissionFailed(_) => Action::Abandon,
+        }
+    }
+
+    /// Short name used as metric label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PipelineError::RpcError(_) => "rpc_error",
+            PipelineError::AskNotFound(_) => "ask_not_found",
+            PipelineError::AskIdOutOfRange(_) => "ask_id_out_of_range",
+            PipelineError::DecryptionFailed(_) => "decryption_failed",
+            PipelineError::DecompressionFailed(_) => "decompression_failed",
+            PipelineError::GeneratorUnavailable(_) => "generator_unavailable",
+            PipelineError::GeneratorRejected(_) => "generator_rejected",
+            PipelineError::IvsFailed(_) => "ivs_failed",
+            PipelineError::SubmissionFailed(_) => "submission_failed",
+        }
+    }
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::RpcError(err) => write!(f, "RPC request failed: {}", err),
+            PipelineError::AskNotFound(ask_id) => {
+                write!(f, "AskCreated event of ask {} not found", ask_id)
+            }
+            PipelineError::AskIdOutOfRange(ask_id) => {
+                write!(f, "Ask id {} doesn't fit in a u64", ask_id)
+            }
+            PipelineError::DecryptionFailed(err) => {
+                write!(f, "Unable to decrypt the secret inputs: {}", err)
+            }
+            PipelineError::DecompressionFailed(err) => {
+                write!(f, "Unable to decompress the secret inputs: {}", err)
+            }
+            PipelineError::GeneratorUnavailable(err) => write!(f, "Generator unavailable: {}", err),
+            PipelineError::GeneratorRejected(err) => {
+                write!(f, "Generator rejected the inputs: {}", err)
+            }
+            PipelineError::IvsFailed(err) => write!(f, "IVS request failed: {}", err),
+            PipelineError::SubmissionFailed(err) => write!(f, "Proof submission failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
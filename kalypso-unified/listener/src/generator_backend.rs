@@ -0,0 +1,383 @@
+use crate::generator_pool::GeneratorPool;
+use crate::MarketDetails;
+use async_trait::async_trait;
+use bindings::shared_types::Ask;
+use ethers::types::{Bytes, U256};
+use http_body_util::{BodyExt, Full};
+use hyper_util::rt::TokioIo;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const GENERATE_PROOF_PATH: &str = "/api/generateProof";
+const HEALTH_CHECK_PATH: &str = "/api/test";
+const CANCEL_PROOF_PATH: &str = "/api/cancelProof";
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How the listener reaches the generator of a market.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GeneratorBackendConfig {
+    // generator reachable over http(s), e.g. on another machine
+    Http {
+        url: String,
+        // sent with every request, e.g. {"Authorization": "Bearer <token>"}
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        // PEM encoded CA certificate to trust in addition to the system roots
+        ca_cert: Option<String>,
+    },
+    // generator listening on a unix domain socket on this machine
+    UnixSocket {
+        path: String,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GeneratorRequest {
+    pub ask: Ask,
+    pub private_input: Vec<u8>,
+    pub ask_id: u64,
+    // when the proof is of no use anymore, not sent to the generator
+    #[serde(skip)]
+    pub deadline: Option<Instant>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CancelRequest {
+    // decimal, ask ids are uint256 on-chain
+    pub ask_id: String,
+}
+
+impl CancelRequest {
+    pub fn new(ask_id: U256) -> Self {
+        Self {
+            ask_id: ask_id.to_string(),
+        }
+    }
+}
+
+/// Reply of a generator. When `success` is false the generator refused the inputs and `data`
+/// carries its signature over the invalid inputs (if it produced one).
+#[derive(Deserialize, Debug, Clone)]
+pub struct GeneratorResponse {
+    #[serde(skip)]
+    pub success: bool,
+    pub message: String,
+    // generators respond with `null` when they refuse inputs without signing them
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub data: Bytes,
+}
+
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+    Ok(Option::<Bytes>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[async_trait]
+pub trait GeneratorBackend: Send + Sync {
+    async fn generate_proof(
+        &self,
+        request: GeneratorRequest,
+    ) -> Result<GeneratorResponse, Box<dyn Error>>;
+
+    // resolves if the generator is reachable and serving requests
+    async fn health_check(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    // asks the generator to stop proving the ask, backends that can't cancel just let it run
+    async fn cancel(&self, _ask_id: U256) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    // where the requests go, for logging
+    fn endpoint(&self) -> String;
+}
+
+pub struct HttpBackend {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpBackend {
+    pub fn new(
+        url: &str,
+        headers: &HashMap<String, String>,
+        ca_cert: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut default_headers = HeaderMap::new();
+        for (name, value) in headers {
+            let mut value = HeaderValue::from_str(value)?;
+            // keeps auth tokens out of debug output
+            value.set_sensitive(true);
+            default_headers.insert(HeaderName::from_bytes(name.as_bytes())?, value);
+        }
+
+        let mut builder = reqwest::Client::builder().default_headers(default_headers);
+        if let Some(ca_cert) = ca_cert {
+            let pem = std::fs::read(ca_cert)?;
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            base_url: url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl GeneratorBackend for HttpBackend {
+    async fn generate_proof(
+        &self,
+        request: GeneratorRequest,
+    ) -> Result<GeneratorResponse, Box<dyn Error>> {
+        let response = self
+            .client
+            .post(self.endpoint())
+            .json(&request)
+            .send()
+            .await?;
+        let status = response.status().as_u16();
+        let body = response.bytes().await?;
+        parse_response(status, &body)
+    }
+
+    async fn health_check(&self) -> Result<(), Box<dyn Error>> {
+        let url = format!("{}{}", self.base_url, HEALTH_CHECK_PATH);
+        self.client.get(url).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    async fn cancel(&self, ask_id: U256) -> Result<(), Box<dyn Error>> {
+        let url = format!("{}{}", self.base_url, CANCEL_PROOF_PATH);
+        self.client
+            .post(url)
+            .json(&CancelRequest::new(ask_id))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn endpoint(&self) -> String {
+        format!("{}{}", self.base_url, GENERATE_PROOF_PATH)
+    }
+}
+
+pub struct UnixSocketBackend {
+    path: String,
+}
+
+impl UnixSocketBackend {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
+    }
+
+    // one HTTP/1.1 request per connection, generator calls are long running anyway
+    async fn send(
+        &self,
+        request: hyper::Request<Full<hyper::body::Bytes>>,
+    ) -> Result<(hyper::StatusCode, hyper::body::Bytes), Box<dyn Error>> {
+        let stream = tokio::net::UnixStream::connect(&self.path).await?;
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        let path = self.path.clone();
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                log::error!("Connection to generator socket {} failed: {}", path, err);
+            }
+        });
+
+        let response = sender.send_request(request).await?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        Ok((status, body))
+    }
+}
+
+#[async_trait]
+impl GeneratorBackend for UnixSocketBackend {
+    async fn generate_proof(
+        &self,
+        request: GeneratorRequest,
+    ) -> Result<GeneratorResponse, Box<dyn Error>> {
+        let body = serde_json::to_vec(&request)?;
+        let http_request = hyper::Request::post(GENERATE_PROOF_PATH)
+            .header(hyper::header::HOST, "localhost")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(hyper::body::Bytes::from(body)))?;
+        let (status, body) = self.send(http_request).await?;
+        parse_response(status.as_u16(), &body)
+    }
+
+    async fn health_check(&self) -> Result<(), Box<dyn Error>> {
+        let http_request = hyper::Request::get(HEALTH_CHECK_PATH)
+            .header(hyper::header::HOST, "localhost")
+            .body(Full::new(hyper::body::Bytes::new()))?;
+        let (status, _) = self.send(http_request).await?;
+        if !status.is_success() {
+            return Err(format!("health check returned {}", status).into());
+        }
+        Ok(())
+    }
+
+    async fn cancel(&self, ask_id: U256) -> Result<(), Box<dyn Error>> {
+        let body = serde_json::to_vec(&CancelRequest::new(ask_id))?;
+        let http_request = hyper::Request::post(CANCEL_PROOF_PATH)
+            .header(hyper::header::HOST, "localhost")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(hyper::body::Bytes::from(body)))?;
+        let (status, _) = self.send(http_request).await?;
+        if !status.is_success() {
+            return Err(format!("cancel returned {}", status).into());
+        }
+        Ok(())
+    }
+
+    fn endpoint(&self) -> String {
+        format!("unix:{}{}", self.path, GENERATE_PROOF_PATH)
+    }
+}
+
+pub fn build_backend(
+    config: &GeneratorBackendConfig,
+) -> Result<Arc<dyn GeneratorBackend>, Box<dyn Error>> {
+    let backend: Arc<dyn GeneratorBackend> = match config {
+        GeneratorBackendConfig::Http {
+            url,
+            headers,
+            ca_cert,
+        } => Arc::new(HttpBackend::new(url, headers, ca_cert.as_deref())?),
+        GeneratorBackendConfig::UnixSocket { path } => Arc::new(UnixSocketBackend::new(path)),
+    };
+    Ok(backend)
+}
+
+// The single generator of a market, older configs only name the port of one on this machine
+fn market_backend_config(market: &MarketDetails) -> GeneratorBackendConfig {
+    match &market.generator {
+        Some(config) => config.clone(),
+        None => GeneratorBackendConfig::Http {
+            url: format!("http://localhost:{}", market.port),
+            headers: HashMap::new(),
+            ca_cert: None,
+        },
+    }
+}
+
+/// Builds the generator backend of every configured market, keyed by market id.
+pub fn build_backends(
+    markets: &HashMap<String, MarketDetails>,
+) -> Result<HashMap<String, Arc<dyn GeneratorBackend>>, Box<dyn Error>> {
+    let mut backends = HashMap::new();
+    for (market_id, market) in markets {
+        let backend: Arc<dyn GeneratorBackend> = if market.generators.is_empty() {
+            build_backend(&market_backend_config(market))
+                .map_err(|err| format!("generator of market {}: {}", market_id, err))?
+        } else {
+            let mut instances = vec![];
+            for config in &market.generators {
+                instances.push(
+                    build_backend(config)
+                        .map_err(|err| format!("generator of market {}: {}", market_id, err))?,
+                );
+            }
+            let pool = Arc::new(GeneratorPool::new(
+                instances,
+                market.dispatch.unwrap_or_default(),
+                market.generator_timeout_secs.map(Duration::from_secs),
+            ));
+            GeneratorPool::spawn_health_checks(&pool, HEALTH_CHECK_INTERVAL);
+            pool
+        };
+        log::info!(
+            "Market {} is served by generator at {}",
+            market_id,
+            backend.endpoint()
+        );
+        backends.insert(market_id.clone(), backend);
+    }
+    Ok(backends)
+}
+
+// Server errors and responses that can't be read mean the generator is unavailable. Other
+// statuses with a readable body are its answer, a refusal of the inputs unless 2xx.
+pub(crate) fn parse_response(
+    status: u16,
+    body: &[u8],
+) -> Result<GeneratorResponse, Box<dyn Error>> {
+    if status >= 500 {
+        return Err(format!(
+            "generator failed with status {}: {}",
+            status,
+            String::from_utf8_lossy(body)
+        )
+        .into());
+    }
+    let mut response: GeneratorResponse = serde_json::from_slice(body).map_err(|err| {
+        format!(
+            "unreadable response with status {}: {} ({})",
+            status,
+            err,
+            String::from_utf8_lossy(body)
+        )
+    })?;
+    response.success = (200..300).contains(&status);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_backend_configs() {
+        let market: MarketDetails = serde_json::from_str(
+            r#"{
+                "ivs_url": "http://ivs",
+                "generator": {
+                    "type": "http",
+                    "url": "https://generator.example:6000/",
+                    "headers": {"Authorization": "Bearer secret"}
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            build_backend(&market_backend_config(&market))
+                .unwrap()
+                .endpoint(),
+            "https://generator.example:6000/api/generateProof"
+        );
+
+        let market: MarketDetails =
+            serde_json::from_str(r#"{"port": "5000", "ivs_url": "http://ivs"}"#).unwrap();
+        assert_eq!(
+            build_backend(&market_backend_config(&market))
+                .unwrap()
+                .endpoint(),
+            "http://localhost:5000/api/generateProof"
+        );
+
+        // no prover is linked into the listener
+        assert!(serde_json::from_str::<MarketDetails>(
+            r#"{"ivs_url": "http://ivs", "generator": {"type": "in_process", "prover": "sp1"}}"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn cancel_requests_carry_the_full_ask_id() {
+        let ask_id = U256::from(u64::MAX) + 1;
+        assert_eq!(
+            serde_json::to_string(&CancelRequest::new(ask_id)).unwrap(),
+            r#"{"ask_id":"18446744073709551616"}"#
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use crate::generator_backend::{GeneratorBackend, GeneratorRequest, GeneratorResponse};
+use async_trait::async_trait;
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How a market's requests are spread over its generators.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DispatchStrategy {
+    // the instance with the fewest proofs in progress
+    #[default]
+    LeastLoaded,
+    RoundRobin,
+}
+
+struct Instance {
+    backend: Arc<dyn GeneratorBackend>,
+    in_flight: AtomicUsize,
+    healthy: AtomicBool,
+}
+
+// Counts a request against an instance until the attempt finishes or is cancelled
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl<'a> InFlightGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Several generator instances serving the same market. Requests go to a healthy instance picked
+/// by the dispatch strategy and are retried on the next one if an instance fails or times out.
+pub struct GeneratorPool {
+    instances: Vec<Instance>,
+    strategy: DispatchStrategy,
+    next: AtomicUsize,
+    // time a single instance gets before the request moves on to the next one
+    attempt_timeout: Option<Duration>,
+}
+
+impl GeneratorPool {
+    pub fn new(
+        backends: Vec<Arc<dyn GeneratorBackend>>,
+        strategy: DispatchStrategy,
+        attempt_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            instances: backends
+                .into_iter()
+                .map(|backend| Instance {
+                    backend,
+                    in_flight: AtomicUsize::new(0),
+                    healthy: AtomicBool::new(true),
+                })
+                .collect(),
+            strategy,
+            next: AtomicUsize::new(0),
+            attempt_timeout,
+        }
+    }
+
+    /// Checks every instance on `/api/test` now and then every `interval`, until the pool is
+    /// dropped (e.g. replaced by a config reload).
+    pub fn spawn_health_checks(pool: &Arc<Self>, interval: Duration) {
+        let pool = Arc::downgrade(pool);
+        tokio::spawn(async move {
+            while let Some(pool) = pool.upgrade() {
+                pool.check_health().await;
+                drop(pool);
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+
+    pub async fn check_health(&self) {
+        for instance in &self.instances {
+            let result = instance.backend.health_check().await;
+            let healthy = result.is_ok();
+            let was_healthy = instance.healthy.swap(healthy, Ordering::SeqCst);
+            match result {
+                Err(err) if was_healthy => log::warn!(
+                    "Generator {} failed its health check: {}",
+                    instance.backend.endpoint(),
+                    err
+                ),
+                Ok(()) if !was_healthy => {
+                    log::info!("Generator {} is healthy again", instance.backend.endpoint())
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Instance indices in the order they should be tried, unhealthy instances last
+    fn candidates(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.instances.len()).collect();
+        match self.strategy {
+            DispatchStrategy::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::SeqCst) % order.len().max(1);
+                order.rotate_left(start);
+            }
+            DispatchStrategy::LeastLoaded => {
+                order.sort_by_key(|index| self.instances[*index].in_flight.load(Ordering::SeqCst))
+            }
+        }
+        order.sort_by_key(|index| !self.instances[*index].healthy.load(Ordering::SeqCst));
+        order
+    }
+}
+
+#[async_trait]
+impl GeneratorBackend for GeneratorPool {
+    async fn generate_proof(
+        &self,
+        request: GeneratorRequest,
+    ) -> Result<GeneratorResponse, Box<dyn Error>> {
+        let mut last_error = String::from("no generator configured");
+        for index in self.candidates() {
+            let instance = &self.instances[index];
+            // an attempt gets no more than the time left until the ask's deadline
+            let time_left = request
+                .deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if time_left == Some(Duration::ZERO) {
+                last_error = "the ask's deadline passed".to_string();
+                break;
+            }
+            let timeout = match (self.attempt_timeout, time_left) {
+                (Some(timeout), Some(time_left)) => Some(timeout.min(time_left)),
+                (timeout, time_left) => timeout.or(time_left),
+            };
+            let (attempt, timed_out) = {
+                let _guard = InFlightGuard::new(&instance.in_flight);
+                let generation = instance.backend.generate_proof(request.clone());
+                match timeout {
+                    Some(timeout) => match tokio::time::timeout(timeout, generation).await {
+                        Ok(result) => (result.map_err(|err| err.to_string()), false),
+                        Err(_) => (Err(format!("no response within {:?}", timeout)), true),
+                    },
+                    None => (generation.await.map_err(|err| err.to_string()), false),
+                }
+            };
+            // it would keep proving the ask while the next instance does too
+            if timed_out {
+                if let Err(err) = instance.backend.cancel(request.ask_id.into()).await {
+                    log::warn!(
+                        "Failed to cancel ask {} on generator {}: {}",
+                        request.ask_id,
+                        instance.backend.endpoint(),
+                        err
+                    );
+                }
+            }
+            match attempt {
+                Ok(response) => return Ok(response),
+                // only errors and timeouts get here, a generator refusing the inputs answered
+                Err(err) => {
+                    log::warn!(
+                        "Generator {} failed for ask {}: {}",
+                        instance.backend.endpoint(),
+                        request.ask_id,
+                        err
+                    );
+                    // keep it at the back of the line until the next health check passes
+                    instance.healthy.store(false, Ordering::SeqCst);
+                    last_error = err;
+                }
+            }
+        }
+        Err(format!("every generator failed, last error: {}", last_error).into())
+    }
+
+    async fn health_check(&self) -> Result<(), Box<dyn Error>> {
+        if self
+            .instances
+            .iter()
+            .any(|instance| instance.healthy.load(Ordering::SeqCst))
+        {
+            Ok(())
+        } else {
+            Err("no healthy generator".into())
+        }
+    }
+
+    // the pool doesn't keep track of which instance has the ask, so all of them are told
+    async fn cancel(&self, ask_id: U256) -> Result<(), Box<dyn Error>> {
+        let mut failures = vec![];
+        for instance in &self.instances {
+            if let Err(err) = instance.backend.cancel(ask_id).await {
+                failures.push(format!("{}: {}", instance.backend.endpoint(), err));
+            }
+        }
+        if failures.len() == self.instances.len() && !failures.is_empty() {
+            return Err(failures.join(", ").into());
+        }
+        Ok(())
+    }
+
+    fn endpoint(&self) -> String {
+        let endpoints: Vec<String> = self
+            .instances
+            .iter()
+            .map(|instance| instance.backend.endpoint())
+            .collect();
+        format!("{:?} ({:?})", endpoints, self.strategy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bindings::shared_types::Ask;
+    use ethers::types::Bytes;
+
+    struct TestBackend {
+        name: &'static str,
+        fails: bool,
+        delay: Duration,
+        cancelled: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl GeneratorBackend for TestBackend {
+        async fn generate_proof(
+            &self,
+            _request: GeneratorRequest,
+        ) -> Result<GeneratorResponse, Box<dyn Error>> {
+            tokio::time::sleep(self.delay).await;
+            if self.fails {
+                return Err("generator crashed".into());
+            }
+            Ok(GeneratorResponse {
+                success: true,
+                message: self.name.to_string(),
+                data: Bytes::new(),
+            })
+        }
+
+        async fn cancel(&self, _ask_id: U256) -> Result<(), Box<dyn Error>> {
+            self.cancelled.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn endpoint(&self) -> String {
+            self.name.to_string()
+        }
+    }
+
+    // a healthy generator refusing the inputs
+    struct Refuses;
+
+    #[async_trait]
+    impl GeneratorBackend for Refuses {
+        async fn generate_proof(
+            &self,
+            _request: GeneratorRequest,
+        ) -> Result<GeneratorResponse, Box<dyn Error>> {
+            Ok(GeneratorResponse {
+                success: false,
+                message: "invalid inputs".to_string(),
+                data: Bytes::new(),
+            })
+        }
+
+        fn endpoint(&self) -> String {
+            "refuses".to_string()
+        }
+    }
+
+    fn test_backend(name: &'static str, fails: bool, delay_ms: u64) -> Arc<TestBackend> {
+        Arc::new(TestBackend {
+            name,
+            fails,
+            delay: Duration::from_millis(delay_ms),
+            cancelled: AtomicUsize::new(0),
+        })
+    }
+
+    fn backend(name: &'static str, fails: bool, delay_ms: u64) -> Arc<dyn GeneratorBackend> {
+        test_backend(name, fails, delay_ms)
+    }
+
+    fn request() -> GeneratorRequest {
+        GeneratorRequest {
+            ask: Ask::default(),
+            private_input: vec![],
+            ask_id: 1,
+            deadline: None,
+        }
+    }
+
+    async fn served_by(pool: &GeneratorPool) -> String {
+        pool.generate_proof(request()).await.unwrap().message
+    }
+
+    #[tokio::test]
+    async fn round_robin_rotates_instances() {
+        let pool = GeneratorPool::new(
+            vec![backend("a", false, 0), backend("b", false, 0)],
+            DispatchStrategy::RoundRobin,
+            None,
+        );
+        assert_eq!(served_by(&pool).await, "a");
+        assert_eq!(served_by(&pool).await, "b");
+        assert_eq!(served_by(&pool).await, "a");
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_instance() {
+        let slow = test_backend("slow", false, 1000);
+        let pool = GeneratorPool::new(
+            vec![
+                backend("crashing", true, 0),
+                slow.clone(),
+                backend("ok", false, 0),
+            ],
+            DispatchStrategy::LeastLoaded,
+            Some(Duration::from_millis(100)),
+        );
+        assert_eq!(served_by(&pool).await, "ok");
+        // the instance that timed out was told to stop
+        assert_eq!(slow.cancelled.load(Ordering::SeqCst), 1);
+        // the failed instances are tried last from now on
+        assert_eq!(pool.candidates(), vec![2, 0, 1]);
+
+        let pool = GeneratorPool::new(
+            vec![backend("crashing", true, 0)],
+            DispatchStrategy::LeastLoaded,
+            None,
+        );
+        assert!(pool.generate_proof(request()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refusals_are_answers_not_failures() {
+        let pool = GeneratorPool::new(
+            vec![Arc::new(Refuses), backend("ok", false, 0)],
+            DispatchStrategy::RoundRobin,
+            None,
+        );
+        let response = pool.generate_proof(request()).await.unwrap();
+        assert!(!response.success);
+        // the refusing instance stays in rotation
+        assert_eq!(served_by(&pool).await, "ok");
+        assert_eq!(pool.candidates(), vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn attempts_end_at_the_deadline() {
+        let slow = test_backend("slow", false, 1000);
+        let pool = GeneratorPool::new(
+            vec![slow.clone(), backend("also slow", false, 1000)],
+            DispatchStrategy::LeastLoaded,
+            Some(Duration::from_secs(10)),
+        );
+        let started = Instant::now();
+        let request = GeneratorRequest {
+            deadline: Some(started + Duration::from_millis(100)),
+            ..request()
+        };
+        assert!(pool.generate_proof(request).await.is_err());
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!(slow.cancelled.load(Ordering::SeqCst), 1);
+    }
+}
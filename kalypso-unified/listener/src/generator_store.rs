@@ -1,12 +1,34 @@
 use ecies::{PublicKey, SecretKey};
 use ethers::{types::Address, types::U256};
 use std::collections::HashMap;
-#[derive(Debug, Clone)]
+use std::fmt;
+
+#[derive(Clone)]
 pub struct Generator {
     pub address: Address,
     pub supported_market_ids: Vec<U256>,
     pub ecies_priv_key: SecretKey,
     pub ecies_pub_key: PublicKey,
+    // asks rewarding less break the generator's acceptance policy
+    pub min_reward: Option<U256>,
+    // stake the generator is willing to have locked by the asks it is proving
+    pub staked_amount: Option<U256>,
+}
+
+// the ECIES private key is left out so generators can be logged
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Generator")
+            .field("address", &self.address)
+            .field("supported_market_ids", &self.supported_market_ids)
+            .field(
+                "ecies_pub_key",
+                &hex::encode(self.ecies_pub_key.serialize()),
+            )
+            .field("min_reward", &self.min_reward)
+            .field("staked_amount", &self.staked_amount)
+            .finish()
+    }
 }
 
 pub struct GeneratorStore {
@@ -24,8 +46,7 @@ impl GeneratorStore {
         self.store.insert(generator.address, generator);
     }
 
-    #[allow(dead_code)]
-    fn remove_generator(&mut self, address: &Address) -> Option<Generator> {
+    pub fn remove_generator(&mut self, address: &Address) -> Option<Generator> {
         self.store.remove(address)
     }
 
@@ -33,11 +54,14 @@ impl GeneratorStore {
         self.store.get(address)
     }
 
+    pub fn generators(&self) -> Vec<Generator> {
+        self.store.values().cloned().collect()
+    }
+
     pub fn count(&self) -> usize {
         self.store.len()
     }
 
-    #[allow(dead_code)]
     pub fn add_supported_market(
         &mut self,
         address: &Address,
@@ -52,7 +76,6 @@ impl GeneratorStore {
         }
     }
 
-    #[allow(dead_code)]
     pub fn remove_supported_market(
         &mut self,
         address: &Address,
@@ -66,4 +89,141 @@ impl GeneratorStore {
             None => Err(format!("No generator found with address: {:?}", address)),
         }
     }
+
+    /// Brings the store in line with `generators`, keeping entries that did not change.
+    /// Returns a summary of what was added, removed or updated.
+    pub fn sync(&mut self, generators: Vec<Generator>) -> Vec<String> {
+        let mut changes = vec![];
+        let wanted: Vec<Address> = generators.iter().map(|g| g.address).collect();
+        let removed: Vec<Address> = self
+            .store
+            .keys()
+            .filter(|address| !wanted.contains(address))
+            .copied()
+            .collect();
+        for address in removed {
+            self.remove_generator(&address);
+            changes.push(format!("removed generator {:?}", address));
+        }
+
+        for generator in generators {
+            let address = generator.address;
+            let existing = match self.store.get(&address) {
+                Some(existing)
+                    if existing.ecies_priv_key.serialize()
+                        == generator.ecies_priv_key.serialize() =>
+                {
+                    existing.supported_market_ids.clone()
+                }
+                Some(_) => {
+                    self.add_generator(generator);
+                    changes.push(format!("replaced ECIES key of generator {:?}", address));
+                    continue;
+                }
+                None => {
+                    self.add_generator(generator);
+                    changes.push(format!("added generator {:?}", address));
+                    continue;
+                }
+            };
+
+            if let Some(current) = self.store.get_mut(&address) {
+                if (current.min_reward, current.staked_amount)
+                    != (generator.min_reward, generator.staked_amount)
+                {
+                    current.min_reward = generator.min_reward;
+                    current.staked_amount = generator.staked_amount;
+                    changes.push(format!("updated policy of generator {:?}", address));
+                }
+            }
+            for market_id in &generator.supported_market_ids {
+                if !existing.contains(market_id) {
+                    // the generator was just looked up, these can't fail
+                    let _ = self.add_supported_market(&address, *market_id);
+                    changes.push(format!(
+                        "generator {:?} joined market {}",
+                        address, market_id
+                    ));
+                }
+            }
+            for market_id in existing {
+                if !generator.supported_market_ids.contains(&market_id) {
+                    let _ = self.remove_supported_market(&address, market_id);
+                    changes.push(format!("generator {:?} left market {}", address, market_id));
+                }
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(address: u64, key: u8, markets: &[u64]) -> Generator {
+        let ecies_priv_key = SecretKey::parse(&[key; 32]).unwrap();
+        Generator {
+            address: Address::from_low_u64_be(address),
+            supported_market_ids: markets.iter().map(|market| U256::from(*market)).collect(),
+            ecies_priv_key,
+            ecies_pub_key: PublicKey::from_secret_key(&ecies_priv_key),
+            min_reward: None,
+            staked_amount: None,
+        }
+    }
+
+    #[test]
+    fn sync_applies_config_changes() {
+        let mut store = GeneratorStore::new();
+        store.add_generator(generator(1, 1, &[1, 2]));
+        store.add_generator(generator(2, 2, &[1]));
+        store.add_generator(generator(3, 3, &[1]));
+
+        let changes = store.sync(vec![
+            generator(1, 1, &[2, 3]),
+            generator(2, 9, &[1]),
+            generator(4, 4, &[5]),
+        ]);
+
+        assert_eq!(changes.len(), 5);
+        assert_eq!(store.count(), 3);
+        assert!(store.get_generator(&Address::from_low_u64_be(3)).is_none());
+        assert_eq!(
+            store
+                .get_generator(&Address::from_low_u64_be(1))
+                .unwrap()
+                .supported_market_ids,
+            vec![U256::from(2), U256::from(3)]
+        );
+        assert_eq!(
+            store
+                .get_generator(&Address::from_low_u64_be(2))
+                .unwrap()
+                .ecies_priv_key
+                .serialize(),
+            [9; 32]
+        );
+        assert!(store
+            .sync(vec![
+                generator(1, 1, &[2, 3]),
+                generator(2, 9, &[1]),
+                generator(4, 4, &[5]),
+            ])
+            .is_empty());
+
+        let mut with_policy = generator(4, 4, &[5]);
+        with_policy.min_reward = Some(U256::from(100));
+        assert_eq!(
+            store.sync(vec![
+                generator(1, 1, &[2, 3]),
+                generator(2, 9, &[1]),
+                with_policy,
+            ]),
+            vec![format!(
+                "updated policy of generator {:?}",
+                Address::from_low_u64_be(4)
+            )]
+        );
+    }
 }
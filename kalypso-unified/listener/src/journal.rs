@@ -0,0 +1,449 @@
+use crate::listener::Proof;
+use bindings::proof_marketplace::AskCreatedFilter;
+use ethers::types::{Address, Bytes, H256, U256, U64};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LAST_PROCESSED_BLOCK_KEY: &[u8] = b"last_processed_block";
+const ASKS_TREE: &str = "asks";
+const BLOCK_HASHES_TREE: &str = "block_hashes";
+const ASK_CREATED_TREE: &str = "ask_created";
+// number of processed block hashes kept around for reorg detection
+const MAX_TRACKED_BLOCKS: usize = 128;
+// AskCreated events kept for asks that may still be assigned to our generators
+const MAX_CACHED_ASKS: usize = 10_000;
+
+/// Lifecycle of an assigned ask as seen by the listener.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AskStage {
+    Seen,
+    ForwardedToGenerator,
+    ProofReceived,
+    Submitted,
+    Confirmed,
+    // submission gave up after exhausting retries or the transaction reverted
+    SubmissionFailed,
+    // the ask can no longer be served (not assigned anymore, failed permanently)
+    Dropped,
+}
+
+impl AskStage {
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            AskStage::Confirmed | AskStage::SubmissionFailed | AskStage::Dropped
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AskRecord {
+    pub ask_id: U256,
+    pub generator: Address,
+    pub new_acl: Bytes,
+    // block of the TaskCreated event, used to roll the ask back on reorgs
+    #[serde(default)]
+    pub block_number: Option<U64>,
+    pub stage: AskStage,
+    pub proof: Option<Proof>,
+    pub transaction_hash: Option<H256>,
+    #[serde(default)]
+    pub failure_reason: Option<String>,
+    pub updated_at: u64,
+}
+
+impl AskRecord {
+    pub fn new(
+        ask_id: U256,
+        generator: Address,
+        new_acl: Bytes,
+        block_number: Option<U64>,
+    ) -> Self {
+        Self {
+            ask_id,
+            generator,
+            new_acl,
+            block_number,
+            stage: AskStage::Seen,
+            proof: None,
+            transaction_hash: None,
+            failure_reason: None,
+            updated_at: now(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedAskCreated {
+    event: AskCreatedFilter,
+    block_number: Option<U64>,
+}
+
+/// On-disk journal of the last processed block and every ask the listener has picked up,
+/// used to resume scanning and re-drive unfinished asks after a restart.
+#[derive(Clone)]
+pub struct Journal {
+    db: sled::Db,
+    asks: sled::Tree,
+    block_hashes: sled::Tree,
+    ask_created: sled::Tree,
+}
+
+impl Journal {
+    pub fn open(path: &str) -> Result<Self, sled::Error> {
+        let db = sled::open(path)?;
+        Self::from_db(db)
+    }
+
+    /// A journal that is deleted when dropped, for runs that must not leave state behind.
+    pub fn temporary() -> Result<Self, sled::Error> {
+        Self::from_db(sled::Config::new().temporary(true).open()?)
+    }
+
+    fn from_db(db: sled::Db) -> Result<Self, sled::Error> {
+        let asks = db.open_tree(ASKS_TREE)?;
+        let block_hashes = db.open_tree(BLOCK_HASHES_TREE)?;
+        let ask_created = db.open_tree(ASK_CREATED_TREE)?;
+        Ok(Self {
+            db,
+            asks,
+            block_hashes,
+            ask_created,
+        })
+    }
+
+    pub fn last_processed_block(&self) -> Result<Option<U64>, sled::Error> {
+        Ok(self
+            .db
+            .get(LAST_PROCESSED_BLOCK_KEY)?
+            .map(|value| U64::from_big_endian(&value)))
+    }
+
+    pub fn set_last_processed_block(&self, block: U64) -> Result<(), sled::Error> {
+        self.db
+            .insert(LAST_PROCESSED_BLOCK_KEY, &block_key(block))?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Remembers the hash of a processed block, keeping only the latest `MAX_TRACKED_BLOCKS`.
+    pub fn record_block_hash(&self, block: U64, hash: H256) -> Result<(), sled::Error> {
+        self.block_hashes
+            .insert(block_key(block), hash.as_bytes())?;
+        while self.block_hashes.len() > MAX_TRACKED_BLOCKS {
+            if self.block_hashes.pop_min()?.is_none() {
+                break;
+            }
+        }
+        self.block_hashes.flush()?;
+        Ok(())
+    }
+
+    /// Tracked block hashes, newest first.
+    pub fn tracked_block_hashes(&self) -> Result<Vec<(U64, H256)>, sled::Error> {
+        let mut hashes = vec![];
+        for entry in self.block_hashes.iter().rev() {
+            let (key, value) = entry?;
+            hashes.push((U64::from_big_endian(&key), H256::from_slice(&value)));
+        }
+        Ok(hashes)
+    }
+
+    /// Rewinds the journal to `fork_block` after a reorg: forgets block hashes above it and
+    /// every ask from a reorged block that was not submitted yet, so those TaskCreated events
+    /// are processed again. Returns the forgotten ask ids.
+    pub fn rollback_to(&self, fork_block: U64) -> Result<Vec<U256>, sled::Error> {
+        for entry in self.block_hashes.range(block_key(fork_block + 1)..) {
+            let (key, _) = entry?;
+            self.block_hashes.remove(key)?;
+        }
+
+        for entry in self.ask_created.iter() {
+            let (key, value) = entry?;
+            if serde_json::from_slice::<CachedAskCreated>(&value)
+                .is_ok_and(|cached| cached.block_number.is_some_and(|block| block > fork_block))
+            {
+                self.ask_created.remove(key)?;
+            }
+        }
+
+        let mut rolled_back = vec![];
+        for entry in self.asks.iter() {
+            let (key, value) = entry?;
+            let record: AskRecord = match serde_json::from_slice(&value) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            let reorged = record.block_number.is_some_and(|block| block > fork_block);
+            let submitted = matches!(record.stage, AskStage::Submitted | AskStage::Confirmed);
+            if reorged && !submitted {
+                self.asks.remove(key)?;
+                rolled_back.push(record.ask_id);
+            }
+        }
+
+        self.set_last_processed_block(fork_block)?;
+        self.block_hashes.flush()?;
+        self.asks.flush()?;
+        self.ask_created.flush()?;
+        Ok(rolled_back)
+    }
+
+    /// Caches an AskCreated event, keeping only the `MAX_CACHED_ASKS` newest asks.
+    pub fn cache_ask_created(
+        &self,
+        event: &AskCreatedFilter,
+        block_number: Option<U64>,
+    ) -> Result<(), sled::Error> {
+        let value = serde_json::to_vec(&CachedAskCreated {
+            event: event.clone(),
+            block_number,
+        })
+        .expect("ask created event is serializable");
+        self.ask_created.insert(ask_key(event.ask_id), value)?;
+        // ask ids are sequential, so the smallest keys are the oldest asks
+        while self.ask_created.len() > MAX_CACHED_ASKS {
+            if self.ask_created.pop_min()?.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn cached_ask_created(
+        &self,
+        ask_id: U256,
+    ) -> Result<Option<AskCreatedFilter>, sled::Error> {
+        Ok(self
+            .ask_created
+            .get(ask_key(ask_id))?
+            .and_then(|value| serde_json::from_slice::<CachedAskCreated>(&value).ok())
+            .map(|cached| cached.event))
+    }
+
+    pub fn remove_cached_ask_created(&self, ask_id: U256) -> Result<(), sled::Error> {
+        self.ask_created.remove(ask_key(ask_id))?;
+        Ok(())
+    }
+
+    /// Forgets an ask whose TaskCreated event was removed, unless it was already submitted.
+    pub fn remove_unsubmitted_ask(&self, ask_id: U256) -> Result<bool, sled::Error> {
+        match self.get_ask(ask_id)? {
+            Some(record) if !matches!(record.stage, AskStage::Submitted | AskStage::Confirmed) => {
+                self.asks.remove(ask_key(ask_id))?;
+                self.asks.flush()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    pub fn get_ask(&self, ask_id: U256) -> Result<Option<AskRecord>, sled::Error> {
+        match self.asks.get(ask_key(ask_id))? {
+            Some(value) => Ok(serde_json::from_slice(&value).ok()),
+            None => Ok(None),
+        }
+    }
+
+    /// Records a newly seen ask. Returns `false` if the ask is already journaled.
+    pub fn record_seen(&self, record: &AskRecord) -> Result<bool, sled::Error> {
+        if self.asks.contains_key(ask_key(record.ask_id))? {
+            return Ok(false);
+        }
+        self.put_ask(record)?;
+        Ok(true)
+    }
+
+    pub fn update_stage(&self, ask_id: U256, stage: AskStage) -> Result<(), sled::Error> {
+        self.update_ask(ask_id, |record| record.stage = stage)
+    }
+
+    pub fn record_proof(&self, ask_id: U256, proof: Proof) -> Result<(), sled::Error> {
+        self.update_ask(ask_id, |record| {
+            record.stage = AskStage::ProofReceived;
+            record.proof = Some(proof);
+        })
+    }
+
+    pub fn record_submission(&self, ask_id: U256, tx_hash: H256) -> Result<(), sled::Error> {
+        self.update_ask(ask_id, |record| {
+            record.stage = AskStage::Submitted;
+            record.transaction_hash = Some(tx_hash);
+        })
+    }
+
+    pub fn record_failure(&self, ask_id: U256, reason: String) -> Result<(), sled::Error> {
+        self.update_ask(ask_id, |record| {
+            record.stage = AskStage::SubmissionFailed;
+            record.failure_reason = Some(reason);
+        })
+    }
+
+    pub fn record_dropped(&self, ask_id: U256, reason: String) -> Result<(), sled::Error> {
+        self.update_ask(ask_id, |record| {
+            record.stage = AskStage::Dropped;
+            record.failure_reason = Some(reason);
+        })
+    }
+
+    /// All asks that have not reached a terminal stage.
+    pub fn unfinished_asks(&self) -> Result<Vec<AskRecord>, sled::Error> {
+        let mut unfinished = vec![];
+        for entry in self.asks.iter() {
+            let (_, value) = entry?;
+            match serde_json::from_slice::<AskRecord>(&value) {
+                Ok(record) if !record.stage.is_terminal() => unfinished.push(record),
+                Ok(_) => {}
+                Err(err) => log::error!("Skipping corrupt journal entry: {}", err),
+            }
+        }
+        Ok(unfinished)
+    }
+
+    fn update_ask<F: FnOnce(&mut AskRecord)>(&self, ask_id: U256, f: F) -> Result<(), sled::Error> {
+        match self.get_ask(ask_id)? {
+            Some(mut record) => {
+                f(&mut record);
+                record.updated_at = now();
+                self.put_ask(&record)
+            }
+            None => {
+                log::warn!("Ask {} not found in journal", ask_id);
+                Ok(())
+            }
+        }
+    }
+
+    fn put_ask(&self, record: &AskRecord) -> Result<(), sled::Error> {
+        let value = serde_json::to_vec(record).expect("ask record is serializable");
+        self.asks.insert(ask_key(record.ask_id), value)?;
+        self.asks.flush()?;
+        Ok(())
+    }
+}
+
+fn ask_key(ask_id: U256) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    ask_id.to_big_endian(&mut key);
+    key
+}
+
+fn block_key(block: U64) -> [u8; 8] {
+    let mut key = [0u8; 8];
+    block.to_big_endian(&mut key);
+    key
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_journal() -> Journal {
+        Journal::temporary().unwrap()
+    }
+
+    fn seen(ask_id: u64, block_number: u64) -> AskRecord {
+        AskRecord::new(
+            ask_id.into(),
+            Address::zero(),
+            Bytes::new(),
+            Some(block_number.into()),
+        )
+    }
+
+    #[test]
+    fn tracks_last_processed_block() {
+        let journal = temp_journal();
+        assert_eq!(journal.last_processed_block().unwrap(), None);
+        journal.set_last_processed_block(U64::from(1234)).unwrap();
+        assert_eq!(
+            journal.last_processed_block().unwrap(),
+            Some(U64::from(1234))
+        );
+    }
+
+    #[test]
+    fn unfinished_asks_excludes_terminal_stages() {
+        let journal = temp_journal();
+        assert!(journal.record_seen(&seen(1, 10)).unwrap());
+        assert!(!journal.record_seen(&seen(1, 10)).unwrap());
+        journal.record_seen(&seen(2, 10)).unwrap();
+        journal
+            .record_proof(2.into(), Proof::ValidProof(Bytes::from(vec![1, 2])))
+            .unwrap();
+        journal.record_seen(&seen(3, 10)).unwrap();
+        journal.update_stage(3.into(), AskStage::Confirmed).unwrap();
+
+        let mut unfinished: Vec<U256> = journal
+            .unfinished_asks()
+            .unwrap()
+            .into_iter()
+            .map(|record| record.ask_id)
+            .collect();
+        unfinished.sort();
+        assert_eq!(unfinished, vec![U256::from(1), U256::from(2)]);
+        assert_eq!(
+            journal.get_ask(2.into()).unwrap().unwrap().stage,
+            AskStage::ProofReceived
+        );
+    }
+
+    #[test]
+    fn rollback_forgets_reorged_unsubmitted_asks() {
+        let journal = temp_journal();
+        for block in 1..=5u64 {
+            journal
+                .record_block_hash(block.into(), H256::repeat_byte(block as u8))
+                .unwrap();
+        }
+        journal.record_seen(&seen(1, 2)).unwrap();
+        journal.record_seen(&seen(2, 4)).unwrap();
+        journal.record_seen(&seen(3, 5)).unwrap();
+        journal.record_submission(3.into(), H256::zero()).unwrap();
+
+        let rolled_back = journal.rollback_to(3.into()).unwrap();
+
+        assert_eq!(rolled_back, vec![U256::from(2)]);
+        assert!(journal.get_ask(1.into()).unwrap().is_some());
+        assert!(journal.get_ask(3.into()).unwrap().is_some());
+        assert_eq!(journal.last_processed_block().unwrap(), Some(3.into()));
+        assert_eq!(
+            journal.tracked_block_hashes().unwrap()[0],
+            (U64::from(3), H256::repeat_byte(3))
+        );
+    }
+
+    #[test]
+    fn caches_ask_created_until_reorged() {
+        let journal = temp_journal();
+        let event = |ask_id: u64| AskCreatedFilter {
+            ask_id: ask_id.into(),
+            has_private_inputs: true,
+            secret_data: Bytes::from(vec![ask_id as u8]),
+            acl: Bytes::new(),
+        };
+        journal
+            .cache_ask_created(&event(1), Some(2.into()))
+            .unwrap();
+        journal
+            .cache_ask_created(&event(2), Some(6.into()))
+            .unwrap();
+
+        assert_eq!(
+            journal.cached_ask_created(1.into()).unwrap(),
+            Some(event(1))
+        );
+        assert_eq!(journal.cached_ask_created(3.into()).unwrap(), None);
+
+        journal.rollback_to(5.into()).unwrap();
+        assert!(journal.cached_ask_created(1.into()).unwrap().is_some());
+        assert!(journal.cached_ask_created(2.into()).unwrap().is_none());
+    }
+}
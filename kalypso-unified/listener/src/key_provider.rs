@@ -0,0 +1,379 @@
+use async_trait::async_trait;
+use ethers::prelude::*;
+use ethers::signers::to_eip155_v;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::transaction::eip712::Eip712;
+use ethers::utils::hash_message;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+/// A secret read from a config file or the environment that never shows up in logs.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// Where a private key comes from when it is not written into the config in plain hex.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeySource {
+    // encrypted JSON v3 keystore, the password is read from an env variable or a file
+    Keystore {
+        path: String,
+        password_env: Option<String>,
+        password_file: Option<String>,
+    },
+    // hex encoded key in an env variable
+    Env {
+        variable: String,
+    },
+    // signing agent on a unix socket that holds the key, only usable for the gas payer
+    Agent {
+        socket: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct KeyError(String);
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for KeyError {}
+
+impl From<WalletError> for KeyError {
+    fn from(err: WalletError) -> Self {
+        KeyError(err.to_string())
+    }
+}
+
+/// Reads a raw private key, either given in plain hex or from `source`.
+pub fn load_private_key(
+    plain: Option<&Secret>,
+    source: Option<&KeySource>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let key = match source {
+        Some(KeySource::Keystore {
+            path,
+            password_env,
+            password_file,
+        }) => {
+            let password = match (password_env, password_file) {
+                (Some(variable), _) => std::env::var(variable)
+                    .map_err(|_| format!("keystore password variable {} is not set", variable))?,
+                (None, Some(file)) => std::fs::read_to_string(file)?.trim_end().to_string(),
+                (None, None) => return Err("keystore needs password_env or password_file".into()),
+            };
+            let wallet = LocalWallet::decrypt_keystore(path, password)
+                .map_err(|err| format!("unable to decrypt keystore {}: {}", path, err))?;
+            return Ok(wallet.signer().to_bytes().to_vec());
+        }
+        Some(KeySource::Env { variable }) => std::env::var(variable)
+            .map(Secret::new)
+            .map_err(|_| format!("key variable {} is not set", variable))?,
+        Some(KeySource::Agent { .. }) => {
+            return Err("keys held by a signing agent can't be exported".into())
+        }
+        None => plain.cloned().ok_or("no private key configured")?,
+    };
+    Ok(hex::decode(key.expose().trim_start_matches("0x"))
+        .map_err(|_| "private key is not valid hex")?)
+}
+
+/// Gas payer signer, backed by a local key or by a signing agent.
+#[derive(Debug, Clone)]
+pub enum ListenerSigner {
+    Local(LocalWallet),
+    Agent(AgentSigner),
+}
+
+impl ListenerSigner {
+    pub async fn new(
+        plain: Option<&Secret>,
+        source: Option<&KeySource>,
+        chain_id: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        if let Some(KeySource::Agent { socket }) = source {
+            return Ok(ListenerSigner::Agent(
+                AgentSigner::connect(socket, chain_id).await?,
+            ));
+        }
+        let key = load_private_key(plain, source)?;
+        let wallet = LocalWallet::from_bytes(&key)?.with_chain_id(chain_id);
+        Ok(ListenerSigner::Local(wallet))
+    }
+}
+
+#[async_trait]
+impl Signer for ListenerSigner {
+    type Error = KeyError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: S,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            ListenerSigner::Local(wallet) => Ok(wallet.sign_message(message).await?),
+            ListenerSigner::Agent(agent) => agent.sign_hash(hash_message(message)).await,
+        }
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, Self::Error> {
+        match self {
+            ListenerSigner::Local(wallet) => Ok(wallet.sign_transaction(tx).await?),
+            ListenerSigner::Agent(agent) => agent.sign_transaction(tx).await,
+        }
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(
+        &self,
+        payload: &T,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            ListenerSigner::Local(wallet) => Ok(wallet.sign_typed_data(payload).await?),
+            ListenerSigner::Agent(agent) => {
+                let encoded = payload
+                    .encode_eip712()
+                    .map_err(|err| KeyError(err.to_string()))?;
+                agent.sign_hash(H256::from(encoded)).await
+            }
+        }
+    }
+
+    fn address(&self) -> Address {
+        match self {
+            ListenerSigner::Local(wallet) => wallet.address(),
+            ListenerSigner::Agent(agent) => agent.address,
+        }
+    }
+
+    fn chain_id(&self) -> u64 {
+        match self {
+            ListenerSigner::Local(wallet) => wallet.chain_id(),
+            ListenerSigner::Agent(agent) => agent.chain_id,
+        }
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        match self {
+            ListenerSigner::Local(wallet) => ListenerSigner::Local(wallet.with_chain_id(chain_id)),
+            ListenerSigner::Agent(agent) => ListenerSigner::Agent(AgentSigner {
+                chain_id: chain_id.into(),
+                ..agent
+            }),
+        }
+    }
+}
+
+/// Signs with a key held by a local agent. The agent reads one JSON request per line on its
+/// unix socket and answers with one JSON line:
+///
+/// `{"method": "address"}` -> `{"address": "0x..."}`
+/// `{"method": "sign_hash", "hash": "0x..."}` -> `{"signature": "0x<r><s><v>"}`
+///
+/// and `{"error": "..."}` on failure.
+#[derive(Debug, Clone)]
+pub struct AgentSigner {
+    socket: String,
+    address: Address,
+    chain_id: u64,
+}
+
+#[derive(Deserialize, Default)]
+struct AgentResponse {
+    address: Option<String>,
+    signature: Option<String>,
+    error: Option<String>,
+}
+
+impl AgentSigner {
+    pub async fn connect(socket: &str, chain_id: u64) -> Result<Self, KeyError> {
+        let response = request(socket, serde_json::json!({ "method": "address" })).await?;
+        let address = response
+            .address
+            .ok_or_else(|| KeyError("agent returned no address".to_string()))?;
+        let address = Address::from_str(&address)
+            .map_err(|err| KeyError(format!("agent address: {}", err)))?;
+        log::info!("Signing with agent at {} for {:?}", socket, address);
+        Ok(Self {
+            socket: socket.to_string(),
+            address,
+            chain_id,
+        })
+    }
+
+    async fn sign_hash(&self, hash: H256) -> Result<Signature, KeyError> {
+        let response = request(
+            &self.socket,
+            serde_json::json!({ "method": "sign_hash", "hash": hash }),
+        )
+        .await?;
+        let signature = response
+            .signature
+            .ok_or_else(|| KeyError("agent returned no signature".to_string()))?;
+        let mut signature = Signature::from_str(&signature)
+            .map_err(|err| KeyError(format!("agent signature: {}", err)))?;
+        if signature.v < 27 {
+            signature.v += 27;
+        }
+        // never hand out a signature for a different key than the one announced
+        if signature.recover(hash).ok() != Some(self.address) {
+            return Err(KeyError(
+                "agent signature does not match its address".to_string(),
+            ));
+        }
+        Ok(signature)
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, KeyError> {
+        // same as a local wallet: the chain id in the sighash has to match the one in v
+        let chain_id = tx.chain_id().map(|id| id.as_u64()).unwrap_or(self.chain_id);
+        let mut tx = tx.clone();
+        tx.set_chain_id(chain_id);
+        let mut signature = self.sign_hash(tx.sighash()).await?;
+        signature.v = to_eip155_v(signature.v as u8 - 27, chain_id);
+        Ok(signature)
+    }
+}
+
+async fn request(socket: &str, body: serde_json::Value) -> Result<AgentResponse, KeyError> {
+    let agent_error = |err: std::io::Error| KeyError(format!("signing agent {}: {}", socket, err));
+    let mut stream = UnixStream::connect(socket).await.map_err(agent_error)?;
+    let mut line = body.to_string();
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .await
+        .map_err(agent_error)?;
+
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .await
+        .map_err(agent_error)?;
+    let response: AgentResponse = serde_json::from_str(&response)
+        .map_err(|err| KeyError(format!("invalid agent response: {}", err)))?;
+    match response.error {
+        Some(err) => Err(KeyError(format!("signing agent {}: {}", socket, err))),
+        None => Ok(response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted() {
+        let secret = Secret::new("8d13b631b2d10d0ea70ad06beb22b97e".to_string());
+        assert_eq!(format!("{:?}", secret), "<redacted>");
+        assert_eq!(format!("{:?}", Some(secret)), "Some(<redacted>)");
+    }
+
+    #[test]
+    fn reads_keys_from_keystore_and_env() {
+        let dir = std::env::temp_dir().join(format!("listener-keystore-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut rng = ethers::core::rand::thread_rng();
+        let (wallet, file) = LocalWallet::new_keystore(&dir, &mut rng, "password", None).unwrap();
+        std::env::set_var("LISTENER_TEST_KEYSTORE_PASSWORD", "password");
+
+        let key = load_private_key(
+            None,
+            Some(&KeySource::Keystore {
+                path: dir.join(file).to_string_lossy().to_string(),
+                password_env: Some("LISTENER_TEST_KEYSTORE_PASSWORD".to_string()),
+                password_file: None,
+            }),
+        )
+        .unwrap();
+        assert_eq!(key, wallet.signer().to_bytes().to_vec());
+
+        std::env::set_var("LISTENER_TEST_KEY", hex::encode(&key));
+        let from_env = load_private_key(
+            None,
+            Some(&KeySource::Env {
+                variable: "LISTENER_TEST_KEY".to_string(),
+            }),
+        )
+        .unwrap();
+        assert_eq!(from_env, key);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn signs_transactions_through_an_agent() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let socket =
+            std::env::temp_dir().join(format!("listener-agent-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        let agent_wallet = wallet.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (reader, mut writer) = stream.into_split();
+                let mut line = String::new();
+                BufReader::new(reader).read_line(&mut line).await.unwrap();
+                let request: serde_json::Value = serde_json::from_str(&line).unwrap();
+                let response = match request["method"].as_str() {
+                    Some("address") => serde_json::json!({ "address": agent_wallet.address() }),
+                    _ => {
+                        let hash: H256 = serde_json::from_value(request["hash"].clone()).unwrap();
+                        let signature = agent_wallet.sign_hash(hash).unwrap();
+                        serde_json::json!({ "signature": signature.to_string() })
+                    }
+                };
+                writer
+                    .write_all(format!("{}\n", response).as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let signer = ListenerSigner::new(
+            None,
+            Some(&KeySource::Agent {
+                socket: socket.to_string_lossy().to_string(),
+            }),
+            421614,
+        )
+        .await
+        .unwrap();
+        assert_eq!(signer.address(), wallet.address());
+
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .to(Address::zero())
+            .nonce(1)
+            .into();
+        let signature = signer.sign_transaction(&tx).await.unwrap();
+        let expected = wallet
+            .with_chain_id(421614u64)
+            .sign_transaction(&tx)
+            .await
+            .unwrap();
+        assert_eq!(signature, expected);
+        std::fs::remove_file(socket).unwrap();
+    }
+}
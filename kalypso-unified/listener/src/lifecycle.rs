@@ -0,0 +1,133 @@
+use bindings::proof_marketplace::{
+    AskCancelledFilter, ProofCreatedFilter, ProofNotGeneratedFilter,
+};
+use ethers::prelude::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// Why an ask no longer needs a proof from this listener.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AskClosed {
+    // a proof was submitted, e.g. by another listener of the same generator
+    ProofCreated,
+    // the requester took the ask back after its deadline
+    Cancelled,
+    // the deadline passed and the generator was slashed for it
+    Slashed,
+}
+
+impl AskClosed {
+    // used in logs, the journal and as metric label
+    pub fn label(&self) -> &'static str {
+        match self {
+            AskClosed::ProofCreated => "proof_created",
+            AskClosed::Cancelled => "ask_cancelled",
+            AskClosed::Slashed => "slashed",
+        }
+    }
+}
+
+/// Signatures of the marketplace events that close an ask.
+pub fn topics() -> Vec<H256> {
+    vec![
+        ProofCreatedFilter::signature(),
+        AskCancelledFilter::signature(),
+        ProofNotGeneratedFilter::signature(),
+    ]
+}
+
+/// Logs of the marketplace that close an ask.
+pub fn filter(marketplace: Address) -> Filter {
+    Filter::new().address(marketplace).topic0(topics())
+}
+
+/// The ask a marketplace log closes, `None` for other logs. Every closing event has the ask id
+/// as its first indexed field.
+pub fn closed_ask(log: &Log) -> Option<(U256, AskClosed)> {
+    let reason = match *log.topics.first()? {
+        topic if topic == ProofCreatedFilter::signature() => AskClosed::ProofCreated,
+        topic if topic == AskCancelledFilter::signature() => AskClosed::Cancelled,
+        topic if topic == ProofNotGeneratedFilter::signature() => AskClosed::Slashed,
+        _ => return None,
+    };
+    let ask_id = U256::from_big_endian(log.topics.get(1)?.as_bytes());
+    Some((ask_id, reason))
+}
+
+/// Asks being proven right now, told when the ask gets closed on-chain.
+#[derive(Default)]
+pub struct Watchlist {
+    asks: Mutex<HashMap<U256, watch::Sender<Option<AskClosed>>>>,
+}
+
+impl Watchlist {
+    pub fn watch(&self, ask_id: U256) -> watch::Receiver<Option<AskClosed>> {
+        let (sender, receiver) = watch::channel(None);
+        self.asks.lock().unwrap().insert(ask_id, sender);
+        receiver
+    }
+
+    pub fn unwatch(&self, ask_id: &U256) {
+        if let Ok(mut asks) = self.asks.lock() {
+            asks.remove(ask_id);
+        }
+    }
+
+    /// Tells the task proving the ask to stop, false if it isn't being proven.
+    pub fn close(&self, ask_id: &U256, reason: AskClosed) -> bool {
+        match self.asks.lock().unwrap().get(ask_id) {
+            Some(sender) => sender.send(Some(reason)).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Resolves once the watched ask is closed, never if it is unwatched first.
+pub async fn closed(receiver: &mut watch::Receiver<Option<AskClosed>>) -> AskClosed {
+    loop {
+        if let Some(reason) = *receiver.borrow_and_update() {
+            return reason;
+        }
+        if receiver.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn closes_watched_asks() {
+        let log = Log {
+            topics: vec![AskCancelledFilter::signature(), H256::from_low_u64_be(7)],
+            ..Default::default()
+        };
+        assert_eq!(
+            closed_ask(&log),
+            Some((U256::from(7), AskClosed::Cancelled))
+        );
+        let other = Log {
+            topics: vec![H256::zero(), H256::from_low_u64_be(7)],
+            ..Default::default()
+        };
+        assert_eq!(closed_ask(&other), None);
+
+        let watchlist = Watchlist::default();
+        assert!(!watchlist.close(&U256::from(7), AskClosed::Cancelled));
+        let mut receiver = watchlist.watch(U256::from(7));
+        assert!(watchlist.close(&U256::from(7), AskClosed::Slashed));
+        assert_eq!(closed(&mut receiver).await, AskClosed::Slashed);
+
+        let mut receiver = watchlist.watch(U256::from(8));
+        watchlist.unwatch(&U256::from(8));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), closed(&mut receiver))
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,46 +1,60 @@
+use crate::ask_index;
+use crate::error::{Action, PipelineError};
+use crate::generator_backend::{GeneratorBackend, GeneratorRequest};
+use crate::journal::Journal;
+use crate::key_provider::ListenerSigner;
+use crate::metrics;
 use crate::MarketDetails;
-use bindings::proof_marketplace::ProofMarketplace;
+use bindings::proof_marketplace::{AskCreatedFilter, ProofMarketplace};
 use bindings::shared_types::Ask;
-use ethers::prelude::k256::ecdsa::SigningKey;
 use ethers::prelude::*;
 use flate2::read::ZlibDecoder;
-use reqwest::Response;
 use secret_input_helpers::secret_inputs_helpers::{
     decrypt_data_with_ecies_and_aes, decrypt_ecies, encrypt_ecies,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::error::Error;
 use std::io::Read;
 use std::sync::Arc;
 use std::time::Instant;
-use std::{thread, time::Duration};
 
 // type ProofMarketPlaceContractWs =
-//     Arc<ProofMarketplace<SignerMiddleware<Provider<Ws>, Wallet<SigningKey>>>>;
+//     Arc<ProofMarketplace<SignerMiddleware<Provider<Ws>, ListenerSigner>>>;
 
 type ProofMarketPlaceContractHttp =
-    Arc<ProofMarketplace<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>>;
+    Arc<ProofMarketplace<SignerMiddleware<Provider<Http>, ListenerSigner>>>;
 
 pub struct GenerateProofParams<'a> {
     pub ask_id: ethers::types::U256,
     pub new_acl: ethers::types::Bytes,
     pub proof_market_place_contract_http: ProofMarketPlaceContractHttp,
     pub ecies_private_key: &'a [u8],
+    pub journal: &'a Journal,
     pub start_block: &'a U64,
-    pub end_block: &'a U64,
+    // block of the TaskCreated event, the ask was created in it or before
+    pub task_block: &'a U64,
     pub markets: &'a HashMap<String, MarketDetails>,
+    pub generators: &'a HashMap<String, Arc<dyn GeneratorBackend>>,
+    // when the ask's deadline passes, if it has one
+    pub deadline: Option<Instant>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Proof {
     ValidProof(Bytes),
     InvalidProof(Bytes),
 }
 
-#[derive(Deserialize, Debug)]
-struct ProofGenerationResponse {
-    message: String,
-    data: Bytes,
+/// Source of the signature submitted for asks with invalid inputs.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidInputSigner {
+    // the IVS checks the inputs and signs them in its enclave, the generator needs no signing key
+    #[default]
+    Ivs,
+    // the signature the generator returns with its refusal, falling back to the IVS if it has none
+    Generator,
 }
 
 // Define the response format struct
@@ -63,202 +77,194 @@ struct IvsPublicKeyResponse {
 //Generating proof for the input
 pub async fn generate_proof(
     generate_proof_params: GenerateProofParams<'_>,
-) -> Result<Proof, Box<dyn std::error::Error>> {
+) -> Result<Proof, PipelineError> {
     let GenerateProofParams {
         proof_market_place_contract_http,
         ask_id,
+        journal,
         start_block,
-        end_block,
+        task_block,
         ecies_private_key,
         new_acl,
         markets,
+        generators,
+        deadline,
     } = generate_proof_params;
-    let client = proof_market_place_contract_http.client();
-    let list_of_ask: &Ask = &proof_market_place_contract_http
+    let list_of_ask: Ask = proof_market_place_contract_http
         .list_of_ask(ask_id)
-        .await?
+        .await
+        .map_err(|err| PipelineError::RpcError(err.to_string()))?
         .0;
     let market_id = list_of_ask.market_id;
+    let (market, generator) = match (
+        markets.get(&market_id.to_string()),
+        generators.get(&market_id.to_string()),
+    ) {
+        (Some(market), Some(generator)) => (market, generator),
+        _ => {
+            return Err(PipelineError::GeneratorUnavailable(format!(
+                "no generator configured for market {}",
+                market_id
+            )))
+        }
+    };
 
     let fetching_ask_secret_timer_start = Instant::now();
-    log::info!(
-        "Finding the logs from block {}, for ask: {}",
-        start_block,
-        ask_id
+    let parsed_ask_created_log = ask_index::find_ask_created(
+        &proof_market_place_contract_http,
+        journal,
+        ask_id,
+        *start_block,
+        *task_block,
+    )
+    .await?;
+    let secret_input = decode_secret_input(
+        &parsed_ask_created_log,
+        &new_acl,
+        ecies_private_key,
+        market_id,
     );
 
-    // Looking for ask in a batch range of 9999 blocks, done to avoid the 10000 block limit.
-    let mut end_block = *end_block;
-    let start_block = *start_block;
-    let mut ask_log: Vec<ethers::core::types::Log> = vec![];
-
-    let blocks_at_once = 9999;
-    while start_block <= end_block {
-        let begin = if end_block >= start_block + blocks_at_once {
-            end_block - blocks_at_once
-        } else {
-            start_block
-        };
-
-        // Fetching ask Transaction hash
-        let ask_event_filter = &proof_market_place_contract_http
-            .ask_created_filter()
-            .filter
-            .from_block(begin)
-            .to_block(end_block)
-            .topic1(ask_id);
-
-        let logs = client.get_logs(ask_event_filter).await?;
-        log::info!("Logs found: {:?}", logs.len());
-        if logs.len() == 1 {
-            ask_log = logs;
-            break;
-        }
-        thread::sleep(Duration::from_millis(2000)); // to reduce calls and avoid rate limit (will be problem only for old requests, not new ones)
-        end_block = begin - 1;
-    }
-
-    let parsed_ask_created_log = proof_market_place_contract_http
-        .decode_event::<bindings::proof_marketplace::AskCreatedFilter>(
-            "AskCreated",
-            ask_log[0].topics.clone(),
-            ask_log[0].data.clone(),
-        )
-        .unwrap();
-
-    //Checking if the ask has a secret provided
-    let decoded_secret_input = if parsed_ask_created_log.has_private_inputs {
-        log::info!("Secret input found");
-        // Handling compressed secret inputs
-        let encrypted_secret_input = parsed_ask_created_log.secret_data.to_vec();
-
-        let result = decrypt_data_with_ecies_and_aes(
-            &encrypted_secret_input,
-            &new_acl,
-            ecies_private_key,
-            market_id,
-        );
-
-        result.unwrap()
-    } else {
-        Vec::new()
-    };
-
     let ask_secret_fetch_time = fetching_ask_secret_timer_start.elapsed().as_millis();
+    metrics::SECRET_FETCH_TIME.observe(fetching_ask_secret_timer_start.elapsed().as_secs_f64());
     log::info!(
         "Took {} ms for fetching the secret inputs for the ask",
         ask_secret_fetch_time
     );
 
-    let mut decoder = ZlibDecoder::new(&decoded_secret_input[..]);
-    let mut decoded_secret_input: Vec<u8> = Vec::new();
-    decoder.read_to_end(&mut decoded_secret_input).unwrap();
-
-    // PRIVATE MARKET
-    if markets.contains_key(&market_id.to_string()) && parsed_ask_created_log.has_private_inputs {
-        let generator_port = &markets.get(&market_id.to_string()).unwrap().port;
-        log::info!(
-            "Forwarding inputs for market ID : {:#?} to the generator running on port {:#?}",
-            market_id.to_string(),
-            generator_port
-        );
-        let proof_generation_response = generate_proof_from_generator(
-            list_of_ask.clone(),
-            decoded_secret_input.clone(),
-            generator_port.to_string(),
-            ask_id.as_u64(),
-        )
-        .await?;
-        if proof_generation_response.status().is_success() {
-            let proof_response: ProofGenerationResponse = proof_generation_response.json().await?;
-            log::info!("{:#?}", proof_response.message);
-            return Ok(Proof::ValidProof(proof_response.data));
-        }
-        // else if proof_generation_response.status() == 400
-        else {
-            let proof_response: ProofGenerationResponse = proof_generation_response.json().await?;
+    let proof = match secret_input {
+        Ok(secret_input) => {
             log::info!(
-                "Error message from the generator : {}",
-                proof_response.message
+                "Forwarding inputs for market ID : {:#?} to the generator at {}",
+                market_id.to_string(),
+                generator.endpoint()
             );
-            let signature = &proof_response.data.to_string()[2..];
-            log::info!("Signature : {}", signature);
-            let sign_bytes = hex::decode(signature)?;
-            return Ok(Proof::InvalidProof(sign_bytes.into()));
+            prove(
+                generator.as_ref(),
+                GeneratorRequest {
+                    ask: list_of_ask,
+                    private_input: secret_input,
+                    ask_id: u64::try_from(ask_id)
+                        .map_err(|_| PipelineError::AskIdOutOfRange(ask_id))?,
+                    deadline,
+                },
+                market.invalid_input_signer,
+            )
+            .await
         }
-    }
-    // PUBLIC MARKET
-    else if markets.contains_key(&market_id.to_string())
-        && !parsed_ask_created_log.has_private_inputs
-    {
-        let generator_port = &markets.get(&market_id.to_string()).unwrap().port;
-        let ivs_url = &markets.get(&market_id.to_string()).unwrap().ivs_url;
-        log::info!(
-            "Forwarding inputs for market ID : {:#?} to the generator running on port {:#?}",
-            market_id.to_string(),
-            generator_port
-        );
-        let proof_generation_response = generate_proof_from_generator(
-            list_of_ask.clone(),
-            decoded_secret_input.clone(),
-            generator_port.to_string(),
-            ask_id.as_u64(),
-        )
-        .await?;
-        if proof_generation_response.status().is_success() {
-            let proof_response: ProofGenerationResponse = proof_generation_response.json().await?;
-            log::info!("{:#?}", proof_response.message);
-            return Ok(Proof::ValidProof(proof_response.data));
+        Err(err) => Err(err),
+    };
+    match proof {
+        Err(err) if err.action() == Action::SubmitInvalid => {
+            log::warn!(
+                "Ask {}: {}, requesting a signature over the invalid inputs from the IVS",
+                ask_id,
+                err
+            );
+            invalid_input_proof(
+                &market.ivs_url,
+                ask_id,
+                ecies_private_key,
+                &new_acl,
+                &parsed_ask_created_log.secret_data,
+            )
+            .await
         }
-        log::info!("Proof generated is not valid");
-
-        let fetch_ivs_public_key = fetch_ivs_public_keys(ivs_url.to_string()).await?;
-
-        let fetch_ivs_public_key_response_body: IvsPublicKeyResponse =
-            fetch_ivs_public_key.json().await?;
-
-        let trimmed_ecies_key = &fetch_ivs_public_key_response_body.data.ivs_ecies_public_key[2..];
+        proof => proof,
+    }
+}
 
-        log::info!("IVS public key data : {:#?}", trimmed_ecies_key);
+// Decrypts and decompresses the secret inputs of the ask, if it has any
+fn decode_secret_input(
+    ask_created: &AskCreatedFilter,
+    new_acl: &Bytes,
+    ecies_private_key: &[u8],
+    market_id: U256,
+) -> Result<Vec<u8>, PipelineError> {
+    if !ask_created.has_private_inputs {
+        return Ok(Vec::new());
+    }
+    log::info!("Secret input found");
+    let compressed_secret_input = decrypt_data_with_ecies_and_aes(
+        &ask_created.secret_data,
+        new_acl,
+        ecies_private_key,
+        market_id,
+    )
+    .map_err(|err| PipelineError::DecryptionFailed(err.to_string()))?;
+
+    let mut decoder = ZlibDecoder::new(&compressed_secret_input[..]);
+    let mut secret_input: Vec<u8> = Vec::new();
+    decoder
+        .read_to_end(&mut secret_input)
+        .map_err(|err| PipelineError::DecompressionFailed(err.to_string()))?;
+    Ok(secret_input)
+}
 
-        let decoded_ecies = &hex::decode(trimmed_ecies_key)?;
-        log::info!("Decoded the ecies key");
+async fn prove(
+    generator: &dyn GeneratorBackend,
+    request: GeneratorRequest,
+    invalid_input_signer: InvalidInputSigner,
+) -> Result<Proof, PipelineError> {
+    let proof_response = generator
+        .generate_proof(request)
+        .await
+        .map_err(|err| PipelineError::GeneratorUnavailable(err.to_string()))?;
+    if proof_response.success {
+        log::info!("{:#?}", proof_response.message);
+        return Ok(Proof::ValidProof(proof_response.data));
+    }
 
-        let cipher = decrypt_ecies(ecies_private_key, &new_acl)?;
-        log::info!("Cipher generated");
-        let final_acl = encrypt_ecies(decoded_ecies, cipher.as_slice())?;
-        log::info!("Final ACL generated");
-        let encrypted_secret_input = parsed_ask_created_log.secret_data.to_vec();
-        log::info!("Encrypted secret input fetched, fetching signature next");
-        let invalid_proof_signature = get_proof_for_invalid_request(
-            ivs_url.to_string(),
-            ask_id.as_u64(),
-            hex::encode(encrypted_secret_input),
-            hex::encode(final_acl),
-        )
-        .await?;
-        let response_data: IvsResponse = invalid_proof_signature.json().await?;
-        log::info!(
-            "Submitting signature for invalid inputs for ASK ID : {}",
-            response_data.ask_id
-        );
-        let signature = &response_data.signature[2..];
-        log::info!("Signature : {}", signature);
-        let sign_bytes = hex::decode(signature).unwrap();
-        return Ok(Proof::InvalidProof(sign_bytes.into()));
+    log::info!(
+        "Error message from the generator : {}",
+        proof_response.message
+    );
+    if invalid_input_signer == InvalidInputSigner::Generator && !proof_response.data.is_empty() {
+        log::info!("Signature : {}", hex::encode(&proof_response.data));
+        return Ok(Proof::InvalidProof(proof_response.data));
     }
+    Err(PipelineError::GeneratorRejected(proof_response.message))
+}
 
-    Err("Circuit not implemented".into())
+// Has the IVS check the inputs and sign that they are invalid. The ACL is re-encrypted for the
+// IVS so it can decrypt the inputs itself.
+async fn invalid_input_proof(
+    ivs_url: &str,
+    ask_id: U256,
+    ecies_private_key: &[u8],
+    new_acl: &Bytes,
+    encrypted_secret_input: &Bytes,
+) -> Result<Proof, PipelineError> {
+    let cipher = decrypt_ecies(ecies_private_key, new_acl)
+        .map_err(|err| PipelineError::DecryptionFailed(err.to_string()))?;
+    log::info!("Cipher generated");
+
+    let ivs_ecies_public_key = fetch_ivs_public_key(ivs_url)
+        .await
+        .map_err(|err| PipelineError::IvsFailed(err.to_string()))?;
+    let final_acl = encrypt_ecies(&ivs_ecies_public_key, cipher.as_slice())
+        .map_err(|err| PipelineError::IvsFailed(err.to_string()))?;
+    log::info!("Final ACL generated, fetching signature next");
+
+    let ask_id = u64::try_from(ask_id).map_err(|_| PipelineError::AskIdOutOfRange(ask_id))?;
+    let signature = get_proof_for_invalid_request(
+        ivs_url,
+        ask_id,
+        hex::encode(encrypted_secret_input),
+        hex::encode(final_acl),
+    )
+    .await
+    .map_err(|err| PipelineError::IvsFailed(err.to_string()))?;
+    Ok(Proof::InvalidProof(signature.into()))
 }
 
 //Get signature for invalid input from IVS
 async fn get_proof_for_invalid_request(
-    ivs_url: String,
+    ivs_url: &str,
     ask_id: u64,
     secret: String,
     acl: String,
-) -> Result<Response, Box<dyn std::error::Error>> {
-    log::info!("Inside the get proof for invalid request");
+) -> Result<Vec<u8>, Box<dyn Error>> {
     #[derive(Serialize)]
     struct Payload {
         pub ask_id: u64,
@@ -275,57 +281,153 @@ async fn get_proof_for_invalid_request(
         acl,
         encrypted_secret: secret,
     };
-    log::info!("Payload generated");
 
     let ivs_endpoint_to_fetch_signature = format!("{}:3030/checkInputWithSignature", ivs_url);
 
     // Make the POST request
-    let res = client
+    let response_data: IvsResponse = client
         .post(ivs_endpoint_to_fetch_signature)
         .json(&payload)
         .send()
+        .await?
+        .json()
         .await?;
-    Ok(res)
-}
-
-//Forward input to proof generator
-async fn generate_proof_from_generator(
-    ask: Ask,
-    private_input: Vec<u8>,
-    port: String,
-    ask_id: u64,
-) -> Result<Response, Box<dyn std::error::Error>> {
-    #[derive(Serialize, Debug)]
-    struct Payload {
-        ask: Ask,
-        private_input: Vec<u8>,
-        ask_id: u64,
-    }
-
-    let payload = Payload {
-        ask,
-        private_input,
-        ask_id,
-    };
-
-    // Create a client instance
-    let client = reqwest::Client::new();
-
-    let endpoint = format!("http://localhost:{}/api/generateProof", port);
-    log::info!("endpoint : {}", endpoint);
-    // Make the POST request
-    let res = client.post(endpoint).json(&payload).send().await?;
-    Ok(res)
+    log::info!(
+        "Submitting signature for invalid inputs for ASK ID : {}",
+        response_data.ask_id
+    );
+    let signature = response_data.signature.trim_start_matches("0x");
+    log::info!("Signature : {}", signature);
+    Ok(hex::decode(signature)?)
 }
 
 //Fetch IVS public keys
-async fn fetch_ivs_public_keys(ivs_url: String) -> Result<Response, Box<dyn std::error::Error>> {
+async fn fetch_ivs_public_key(ivs_url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
     // Create a client instance
     let client = reqwest::Client::new();
 
     let fetch_ivs_public_key_endpoint =
         format!("{}:5000/api/fetchInputVerifierPublicKeys", ivs_url);
     // Make the POST request
-    let res = client.post(fetch_ivs_public_key_endpoint).send().await?;
-    Ok(res)
+    let response: IvsPublicKeyResponse = client
+        .post(fetch_ivs_public_key_endpoint)
+        .send()
+        .await?
+        .json()
+        .await?;
+    let ivs_ecies_public_key = response.data.ivs_ecies_public_key;
+    log::info!("IVS public key data : {:#?}", ivs_ecies_public_key);
+    Ok(hex::decode(ivs_ecies_public_key.trim_start_matches("0x"))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator_backend::{parse_response, GeneratorResponse};
+    use async_trait::async_trait;
+
+    // answers every request with the status and body of a generator
+    struct Responds(u16, &'static str);
+
+    #[async_trait]
+    impl GeneratorBackend for Responds {
+        async fn generate_proof(
+            &self,
+            _request: GeneratorRequest,
+        ) -> Result<GeneratorResponse, Box<dyn Error>> {
+            parse_response(self.0, self.1.as_bytes())
+        }
+
+        fn endpoint(&self) -> String {
+            "test".to_string()
+        }
+    }
+
+    async fn prove_with(generator: Responds) -> Result<Proof, PipelineError> {
+        let request = GeneratorRequest {
+            ask: Ask::default(),
+            private_input: vec![],
+            ask_id: 1,
+            deadline: None,
+        };
+        prove(&generator, request, InvalidInputSigner::Ivs).await
+    }
+
+    #[tokio::test]
+    async fn classifies_generator_responses() {
+        let refused = prove_with(Responds(
+            400,
+            r#"{"message": "invalid inputs", "data": null}"#,
+        ))
+        .await;
+        let Err(err) = refused else {
+            panic!("refused inputs were proven");
+        };
+        assert_eq!(
+            err,
+            PipelineError::GeneratorRejected("invalid inputs".to_string())
+        );
+        assert_eq!(err.action(), Action::SubmitInvalid);
+
+        let proven = prove_with(Responds(200, r#"{"message": "ok", "data": "0x01"}"#)).await;
+        assert!(matches!(proven, Ok(Proof::ValidProof(data)) if data.to_vec() == vec![1]));
+
+        for (status, body) in [
+            (503, r#"{"message": "overloaded", "data": null}"#),
+            (502, "Bad Gateway"),
+            (404, "Not Found"),
+        ] {
+            let failed = prove_with(Responds(status, body)).await;
+            assert!(
+                matches!(&failed, Err(PipelineError::GeneratorUnavailable(_))),
+                "{} {}: {:?}",
+                status,
+                body,
+                failed.err()
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_secret_inputs_are_errors() {
+        let ecies_private_key = [7u8; 32];
+        let ecies_public_key = ecies::PublicKey::from_secret_key(
+            &ecies::SecretKey::parse(&ecies_private_key).unwrap(),
+        );
+        let acl: Bytes = encrypt_ecies(&ecies_public_key.serialize(), &[1u8; 32])
+            .unwrap()
+            .into();
+        let ask_created = |has_private_inputs: bool, secret_data: Vec<u8>| AskCreatedFilter {
+            ask_id: 1.into(),
+            has_private_inputs,
+            secret_data: secret_data.into(),
+            acl: Bytes::new(),
+        };
+
+        let public = decode_secret_input(
+            &ask_created(false, vec![]),
+            &acl,
+            &ecies_private_key,
+            1.into(),
+        );
+        assert_eq!(public, Ok(vec![]));
+
+        let bad_acl = decode_secret_input(
+            &ask_created(true, vec![1; 64]),
+            &Bytes::new(),
+            &ecies_private_key,
+            1.into(),
+        );
+        assert!(matches!(bad_acl, Err(PipelineError::DecryptionFailed(_))));
+
+        let bad_secret = decode_secret_input(
+            &ask_created(true, vec![1; 64]),
+            &acl,
+            &ecies_private_key,
+            1.into(),
+        );
+        let err = bad_secret.unwrap_err();
+        assert!(matches!(err, PipelineError::DecryptionFailed(_)));
+        assert_eq!(err.action(), Action::SubmitInvalid);
+    }
 }
@@ -1,24 +1,49 @@
 use bindings::proof_marketplace as pmp;
+use clap::Parser;
 use ethers::prelude::*;
 use ethers::types::U256;
 use ethers::{abi::Address, providers::Provider};
-use listener::GenerateProofParams;
+use journal::Journal;
+use key_provider::{KeySource, ListenerSigner, Secret};
 use openssl::rand::rand_bytes;
-use std::collections::HashMap;
+use processor::AskContext;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::{error::Error, str::FromStr, sync::Arc, thread, time::Duration};
-
+use std::sync::RwLock;
+use std::time::Instant;
+use std::{error::Error, str::FromStr, sync::Arc, time::Duration};
+
+mod cli;
+mod config_reload;
+mod consistency;
+mod error;
+mod generator_backend;
+mod generator_pool;
 mod generator_store;
+mod journal;
+mod key_provider;
+mod lifecycle;
 mod listener;
-
+mod metrics;
+mod processor;
+mod reorg;
+mod scheduler;
+mod shutdown;
+mod submitter;
+mod subscription;
+
+mod acceptance;
 mod ask;
+mod ask_index;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct GeneratorConfigModel {
     address: String,
-    ecies_private_key: String,
+    ecies_private_key: Option<Secret>,
+    // alternative to ecies_private_key
+    ecies_key: Option<KeySource>,
     data: Option<String>,
     supported_markets: Vec<String>,
     staked_amount: Option<U256>,
@@ -32,21 +57,87 @@ struct Config {
 
 #[derive(Serialize, Debug, Deserialize, Clone)]
 pub struct MarketDetails {
+    // port of a generator on this machine, used when `generator` is not set
+    #[serde(default)]
     pub port: String,
+    pub generator: Option<generator_backend::GeneratorBackendConfig>,
+    // several instances serving the market, takes precedence over `generator` and `port`
+    #[serde(default)]
+    pub generators: Vec<generator_backend::GeneratorBackendConfig>,
+    pub dispatch: Option<generator_pool::DispatchStrategy>,
+    // time one instance gets before the request is retried on the next
+    pub generator_timeout_secs: Option<u64>,
     pub ivs_url: String,
+    // who signs that an ask's inputs are invalid, the IVS by default
+    #[serde(default)]
+    pub invalid_input_signer: listener::InvalidInputSigner,
+    // proofs generated concurrently for this market, defaults to max_concurrent_proofs
+    pub max_concurrency: Option<usize>,
+    #[serde(default)]
+    pub acceptance: acceptance::MarketRules,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct RuntimeConfigModel {
+/// A deployment of the Kalypso contracts served by the listener.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ChainConfig {
+    // used in logs, defaults to the chain id
+    name: Option<String>,
     ws_url: Option<String>,
     http_url: String,
-    private_key: String,
+    private_key: Option<Secret>,
+    // alternative to private_key
+    signer: Option<KeySource>,
     proof_market_place: String,
     generator_registry: String,
+    // EntityKeyRegistry holding the generators' ECIES keys, which aren't checked on-chain if unset
+    entity_registry: Option<String>,
     start_block: u64,
     chain_id: u64,
+    journal_path: Option<String>,
+    ingestion_mode: Option<IngestionMode>,
+    confirmations: Option<u64>,
+}
+
+impl ChainConfig {
+    fn name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("chain {}", self.chain_id))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RuntimeConfigModel {
+    // chains served by this listener, a single chain can be configured with the ChainConfig
+    // fields at the top level instead
+    #[serde(default)]
+    chains: Vec<ChainConfig>,
     params_path: String,
+    // shared by all chains, a market id has to stand for the same program on each of them
     markets: HashMap<String, MarketDetails>,
+    // across all chains
+    max_concurrent_proofs: Option<usize>,
+    // serve prometheus metrics on this port, disabled if unset
+    metrics_port: Option<u16>,
+    // how often the config files are checked for changes, 0 disables reloading
+    config_reload_secs: Option<u64>,
+    // how long running asks get to finish on shutdown
+    shutdown_timeout_secs: Option<u64>,
+    #[serde(default)]
+    submitter: submitter::SubmitterConfig,
+    #[serde(default)]
+    consistency_checks: consistency::ConsistencyConfig,
+    #[serde(default)]
+    acceptance: acceptance::AcceptanceConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum IngestionMode {
+    // eth_getLogs over http_url
+    Polling,
+    // eth_subscribe over ws_url, falling back to polling while the subscription is down
+    Subscription,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,297 +145,810 @@ struct RuntimeConfig {
     runtime_config: RuntimeConfigModel,
 }
 
+// runtime_config.json of a listener serving the one chain configured at the top level
+#[derive(Debug, Deserialize)]
+struct SingleChainConfig {
+    runtime_config: ChainConfig,
+}
+
+type SignerClient = SignerMiddleware<Provider<Http>, ListenerSigner>;
+
+const DEFAULT_JOURNAL_PATH: &str = "./listener_journal";
+const DEFAULT_CONFIRMATIONS: u64 = 10;
+const DEFAULT_MAX_CONCURRENT_PROOFS: usize = 20;
+// fallback when the block time can't be measured from the chain
+const DEFAULT_BLOCK_TIME: Duration = Duration::from_millis(250);
+const SUBSCRIPTION_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+const SUBSCRIPTION_HEAD_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_CONFIG_RELOAD_SECS: u64 = 10;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 120;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    let generator_config_path = "./generator_config/generator_config.json".to_string();
-    let alt_generator_config_path = "../generator_config/generator_config.json".to_string();
-    let file_content = fs::read_to_string(&generator_config_path)
-        .or_else(|_| fs::read_to_string(&alt_generator_config_path))?;
+    let cli = cli::Cli::parse();
+    if cli.dry_run {
+        log::warn!("Dry run, proofs will not be submitted");
+    }
 
-    println!("{}", &file_content);
-    let config: Config = serde_json::from_str(&file_content)?;
+    let file_content = read_config(cli.generator_config.as_deref(), "generator_config.json")?;
+    let mut config: Config = serde_json::from_str(&file_content)?;
 
-    let runtime_config_path = "./generator_config/runtime_config.json".to_string();
-    let alt_runtime_config_path = "../generator_config/runtime_config.json".to_string();
-    let file_content = fs::read_to_string(&runtime_config_path)
-        .or_else(|_| fs::read_to_string(&alt_runtime_config_path))?;
-    println!("{}", &file_content);
+    let file_content = read_config(cli.runtime_config.as_deref(), "runtime_config.json")?;
     let runtime_config: RuntimeConfig = serde_json::from_str(&file_content)?;
-    let runtime_config = runtime_config.runtime_config;
-
-    let key = runtime_config.private_key;
-    let chain_id = runtime_config.chain_id;
-
-    let http_url = runtime_config.http_url;
-    let proof_market_place_var = runtime_config.proof_market_place;
-    let markets = Arc::new(runtime_config.markets);
-
-    let signer = key.parse::<LocalWallet>().unwrap().with_chain_id(chain_id);
-    let signer_address = signer.address();
-    log::info!("Gas payers address : {:?}", signer.address());
+    let mut runtime_config = runtime_config.runtime_config;
+    apply_env_overrides(&mut config);
+    let chain_configs = chain_configs(&file_content, std::mem::take(&mut runtime_config.chains))?;
 
-    let provider_http = Provider::<Http>::connect(&http_url)
-        .await
-        .with_signer(signer.clone());
-
-    let client_http = Arc::new(provider_http.clone());
-
-    let wallet_nonce = provider_http
-        .get_transaction_count(signer_address, None)
-        .await?
-        .as_u64();
-    log::info!("Wallet nonce : {}", wallet_nonce);
-
-    let proof_marketplace_address = Address::from_str(&proof_market_place_var)?;
-    let proof_marketplace_http = Arc::new(pmp::ProofMarketplace::new(
-        proof_marketplace_address,
-        Arc::clone(&client_http),
-    ));
-
-    let submitter_pmp = Arc::new(tokio::sync::Mutex::new(pmp::ProofMarketplace::new(
-        proof_marketplace_address,
-        Arc::clone(&client_http),
-    )));
+    let markets = processor::Markets {
+        generators: generator_backend::build_backends(&runtime_config.markets)?,
+        details: runtime_config.markets.clone(),
+    };
 
     let mut key_store = generator_store::GeneratorStore::new();
     for config in config.generator_config {
-        let generator_address = match Address::from_str(&config.address) {
-            Ok(value) => value,
-            Err(_) => {
-                log::error!("Invalid Address for generator provided");
+        let generator = match load_generator(config) {
+            Ok(generator) => generator,
+            Err(err) => {
+                log::error!("{}", err);
                 continue;
             }
         };
 
-        let existing_generator_data = key_store.get_generator(&generator_address);
+        let existing_generator_data = key_store.get_generator(&generator.address);
         if existing_generator_data.is_some() {
             return Err("Generator Address mentioned twice in the network".into());
         }
+        key_store.add_generator(generator);
+    }
 
-        let mut original_message = vec![0; 32]; // for example, 32 bytes
-        rand_bytes(&mut original_message).expect("Failed to generate random bytes");
+    log::info!("Total number of generators {:?}", key_store.count());
 
-        let private_key = hex::decode(&config.ecies_private_key).unwrap();
-        let ecies_secret_key = ecies::SecretKey::parse_slice(&private_key).unwrap();
+    let mut chains = vec![];
+    for config in chain_configs {
+        chains.push(Chain::connect(config, &runtime_config, &cli).await?);
+    }
 
-        let public_key = ecies::PublicKey::from_secret_key(&ecies_secret_key).serialize();
-        let encrypted_message = match ecies::encrypt(&public_key, &original_message) {
-            Ok(value) => value,
-            Err(_) => {
-                log::error!("Unable to encrypt message using public key");
-                continue;
-            }
+    if let Some(cli::Command::Check) = cli.command {
+        let mut reports = serde_json::Map::new();
+        let mut failed = false;
+        for chain in &chains {
+            let chain_reports = chain
+                .consistency_checker
+                .check(&key_store.generators())
+                .await?;
+            failed |= chain_reports
+                .iter()
+                .any(|report| !report.issues().is_empty());
+            reports.insert(chain.name.clone(), serde_json::to_value(&chain_reports)?);
+        }
+        // the reports of a single chain are printed as they are
+        let reports = match reports.len() {
+            1 => reports
+                .into_iter()
+                .next()
+                .map(|(_, reports)| reports)
+                .unwrap_or_default(),
+            _ => serde_json::Value::Object(reports),
         };
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+        if failed {
+            return Err("Some generators failed the on-chain checks".into());
+        }
+        return Ok(());
+    }
 
-        let decrypted_message = match ecies::decrypt(&private_key, &encrypted_message) {
-            Ok(value) => value,
-            Err(_) => {
-                log::error!("Unable to decrypt message using private key");
-                continue;
+    if let Some(metrics_port) = runtime_config.metrics_port {
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(([0, 0, 0, 0], metrics_port).into()).await {
+                log::error!("Metrics server stopped: {}", err);
             }
+        });
+    }
+
+    let should_stop = Arc::new(AtomicBool::new(false));
+    let stop_handle = should_stop.clone();
+    let mut signals = shutdown::ShutdownSignals::new()?;
+    let (signals_tx, signals_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let signal = signals.recv().await;
+        log::info!("Received {}, shutting down", signal);
+        stop_handle.store(true, Ordering::Release);
+        // handed back so a second signal can cut the drain short
+        let _ = signals_tx.send(signals);
+    });
+    let shutdown_timeout = Duration::from_secs(
+        runtime_config
+            .shutdown_timeout_secs
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+    );
+
+    let max_concurrent_proofs = runtime_config
+        .max_concurrent_proofs
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_PROOFS);
+    let market_concurrency = market_concurrency(&markets.details)?;
+
+    let shared = Arc::new(processor::Shared {
+        key_store: RwLock::new(key_store),
+        markets: RwLock::new(Arc::new(markets)),
+        scheduler: scheduler::Scheduler::new(
+            chains.iter().map(|chain| chain.block_time).collect(),
+            max_concurrent_proofs,
+            market_concurrency,
+        ),
+        draining: AtomicBool::new(false),
+        running_jobs: AtomicUsize::new(0),
+    });
+    let mut ask_contexts = vec![];
+    for (index, chain) in chains.iter().enumerate() {
+        ask_contexts.push(
+            chain
+                .start(index, &shared, &runtime_config, cli.dry_run)
+                .await?,
+        );
+    }
+
+    if let Some(cli::Command::Replay {
+        ask_id, chain_id, ..
+    }) = cli.command
+    {
+        let ask_context = match chain_id {
+            Some(chain_id) => chains
+                .iter()
+                .position(|chain| chain.config.chain_id == chain_id)
+                .map(|index| &ask_contexts[index])
+                .ok_or_else(|| format!("Chain {} is not configured", chain_id))?,
+            None if ask_contexts.len() == 1 => &ask_contexts[0],
+            None => return Err("Several chains are configured, pick one with --chain-id".into()),
         };
+        return processor::replay_ask(ask_context, ask_id).await;
+    }
 
-        if original_message != decrypted_message {
-            log::error!("The public and private keys do not match!");
-            continue;
+    for (chain, ask_context) in chains.iter().zip(&ask_contexts) {
+        let reports = consistency::apply(ask_context, &chain.consistency_checker).await?;
+        if chain.consistency_checker.config.mode == consistency::ConsistencyMode::Refuse
+            && !reports.is_empty()
+            && reports
+                .iter()
+                .all(|report| report.failed_markets().len() == report.markets.len())
+        {
+            return Err(
+                format!("No generator passed the on-chain checks on {}", chain.name).into(),
+            );
         }
+        tokio::spawn(consistency::watch(
+            Arc::clone(ask_context),
+            Arc::clone(&chain.consistency_checker),
+        ));
+    }
+    tokio::spawn(config_reload::watch(
+        Arc::clone(&shared),
+        cli.generator_config.clone(),
+        cli.runtime_config.clone(),
+        Duration::from_secs(
+            runtime_config
+                .config_reload_secs
+                .unwrap_or(DEFAULT_CONFIG_RELOAD_SECS),
+        ),
+    ));
+    tokio::spawn(processor::run_dispatcher(
+        Arc::clone(&shared),
+        ask_contexts.clone(),
+    ));
 
-        let mut supported_markets: Vec<U256> = vec![];
+    for ask_context in &ask_contexts {
+        resume_unfinished_asks(ask_context).await?;
+    }
 
-        for market in config.supported_markets.into_iter() {
-            let market_temp = U256::from_dec_str(&market).unwrap();
-            supported_markets.push(market_temp);
-        }
+    // the first chain to fail stops the listener
+    futures::future::try_join_all(
+        chains
+            .iter()
+            .zip(&ask_contexts)
+            .map(|(chain, ask_context)| chain.run(ask_context, &should_stop)),
+    )
+    .await?;
+
+    if let Ok(mut signals) = signals_rx.await {
+        shutdown::drain(&shared, &ask_contexts, &mut signals, shutdown_timeout).await;
+    }
+    Ok(())
+}
 
-        let generator = generator_store::Generator {
-            address: generator_address,
-            supported_market_ids: supported_markets,
-            ecies_priv_key: ecies_secret_key,
-            ecies_pub_key: ecies::PublicKey::from_secret_key(&ecies_secret_key),
+// A chain the listener is connected to
+struct Chain {
+    name: String,
+    config: ChainConfig,
+    client_http: Arc<SignerClient>,
+    proof_marketplace_http: Arc<processor::ProofMarketPlaceContractHttp>,
+    consistency_checker: Arc<consistency::ConsistencyChecker<SignerClient>>,
+    block_time: Duration,
+    // start_block of the config, or where a replay starts searching
+    start_block: U64,
+}
+
+impl Chain {
+    async fn connect(
+        config: ChainConfig,
+        runtime_config: &RuntimeConfigModel,
+        cli: &cli::Cli,
+    ) -> Result<Self, Box<dyn Error>> {
+        let name = config.name();
+        let signer = ListenerSigner::new(
+            config.private_key.as_ref(),
+            config.signer.as_ref(),
+            config.chain_id,
+        )
+        .await
+        .map_err(|err| format!("Unable to load the gas payer key of {}: {}", name, err))?;
+        let signer_address = signer.address();
+        log::info!("Gas payers address on {} : {:?}", name, signer_address);
+
+        let provider_http = Provider::<Http>::connect(&config.http_url)
+            .await
+            .with_signer(signer);
+        let client_http = Arc::new(provider_http);
+
+        let wallet_nonce = client_http
+            .get_transaction_count(signer_address, None)
+            .await?
+            .as_u64();
+        log::info!("Wallet nonce on {} : {}", name, wallet_nonce);
+
+        let proof_marketplace_address = Address::from_str(&config.proof_market_place)?;
+        let proof_marketplace_http = Arc::new(pmp::ProofMarketplace::new(
+            proof_marketplace_address,
+            Arc::clone(&client_http),
+        ));
+
+        let consistency_checker = Arc::new(consistency::ConsistencyChecker::new(
+            Arc::clone(&client_http),
+            Address::from_str(&config.generator_registry)?,
+            config
+                .entity_registry
+                .as_deref()
+                .map(Address::from_str)
+                .transpose()?,
+            runtime_config.consistency_checks.clone(),
+        ));
+
+        let block_to_use = client_http
+            .provider()
+            .get_block_number()
+            .await
+            .unwrap_or(4180050.into());
+        let start_block =
+            U64::from_dec_str(&config.start_block.to_string()).unwrap_or(block_to_use);
+        let start_block = match &cli.command {
+            Some(cli::Command::Replay {
+                from_block: Some(from_block),
+                ..
+            }) => U64::from(*from_block),
+            _ => start_block,
         };
-        key_store.add_generator(generator);
+
+        let block_time = scheduler::estimate_block_time(client_http.as_ref(), 1000)
+            .await
+            .unwrap_or_else(|err| {
+                log::warn!("Unable to measure block time of {}: {}", name, err);
+                DEFAULT_BLOCK_TIME
+            });
+        log::info!("Average block time of {} {:?}", name, block_time);
+
+        Ok(Self {
+            name,
+            config,
+            client_http,
+            proof_marketplace_http,
+            consistency_checker,
+            block_time,
+            start_block,
+        })
     }
 
-    log::info!("Total number of generators {:?}", key_store.count());
+    // Opens the chain's journal and sets up its ask pipeline as chain `index` of the scheduler
+    async fn start(
+        &self,
+        index: usize,
+        shared: &Arc<processor::Shared>,
+        runtime_config: &RuntimeConfigModel,
+        dry_run: bool,
+    ) -> Result<Arc<AskContext>, Box<dyn Error>> {
+        let journal = if dry_run {
+            Journal::temporary()?
+        } else {
+            let journal_path = self
+                .config
+                .journal_path
+                .as_deref()
+                .unwrap_or(DEFAULT_JOURNAL_PATH);
+            log::info!("Using journal at {} for {}", journal_path, self.name);
+            Journal::open(journal_path)?
+        };
 
-    let block_to_use = client_http
-        .provider()
-        .get_block_number()
-        .await
-        .unwrap_or(4180050.into());
-    let runtime_start_block =
-        U64::from_dec_str(&runtime_config.start_block.to_string()).unwrap_or(block_to_use);
-    let mut start_block = runtime_start_block;
+        let ask_context = Arc::new(AskContext {
+            shared: Arc::clone(shared),
+            chain: index,
+            chain_name: self.name.clone(),
+            proof_marketplace_http: Arc::clone(&self.proof_marketplace_http),
+            submitter: submitter::Submitter::new(
+                Arc::clone(&self.client_http),
+                self.proof_marketplace_http.address(),
+                runtime_config.submitter.clone(),
+            ),
+            journal,
+            start_block: self.start_block,
+            in_flight: std::sync::Mutex::new(HashSet::new()),
+            dry_run,
+            refused_markets: RwLock::new(HashSet::new()),
+            acceptance: acceptance::AcceptancePolicy::new(&runtime_config.acceptance),
+            watchlist: lifecycle::Watchlist::default(),
+        });
+        ask_context.set_current_block(self.client_http.get_block_number().await?);
+        Ok(ask_context)
+    }
 
-    let blocks_at_once = 10000;
+    // Scans the chain for asks until a stop is requested
+    async fn run(
+        &self,
+        ask_context: &Arc<AskContext>,
+        should_stop: &AtomicBool,
+    ) -> Result<(), Box<dyn Error>> {
+        let journal = &ask_context.journal;
+        let provider_http = self.client_http.as_ref();
+        let ws_url = &self.config.ws_url;
+        let ingestion_mode = self.config.ingestion_mode.unwrap_or(match ws_url {
+            Some(_) => IngestionMode::Subscription,
+            None => IngestionMode::Polling,
+        });
+        log::info!(
+            "Ingesting TaskCreated events of {} via {:?}",
+            self.name,
+            ingestion_mode
+        );
+        let confirmations = self.config.confirmations.unwrap_or(DEFAULT_CONFIRMATIONS);
+        let blocks_at_once = 10000;
+        let mut subscription_retry_at = Instant::now();
+        // last block scanned for logs that close asks, those are read at the head
+        let mut closing_scanned: Option<U64> = None;
+
+        let mut start_block = match journal.last_processed_block()? {
+            Some(last_processed_block) => {
+                log::info!(
+                    "Resuming {} from journal, last processed block {}",
+                    self.name,
+                    last_processed_block
+                );
+                last_processed_block + 1
+            }
+            None => self.start_block,
+        };
 
-    let should_stop = Arc::new(AtomicBool::new(false));
-    let stop_handle = should_stop.clone();
-    tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.unwrap();
-        stop_handle.store(true, Ordering::Release);
-    });
+        loop {
+            if should_stop.load(Ordering::Acquire) {
+                log::info!("Gracefully shutting down {}...", self.name);
+                break;
+            }
 
-    let thread_count = Arc::new(AtomicUsize::new(0));
-    let max_thread_count = 20;
+            if let Some(resume_block) = self.check_reorg(journal).await? {
+                start_block = resume_block;
+            }
 
-    loop {
-        if should_stop.load(Ordering::Acquire) {
-            log::info!("Gracefully shutting down...");
-            break;
-        }
+            let latest_block = provider_http.get_block_number().await?;
+            ask_context.set_current_block(latest_block);
+            metrics::BLOCK_LAG
+                .with_label_values(&[&self.name])
+                .set((latest_block + 1).saturating_sub(start_block).as_u64() as i64);
+            // only blocks with enough confirmations are processed
+            let safe_block = latest_block.saturating_sub(confirmations.max(1).into());
+
+            // asks closed on-chain are called off as soon as the close is seen, waiting for
+            // confirmations would keep the generator proving them for nothing. Older blocks are
+            // covered by the confirmed scan below.
+            let closing_from = closing_scanned.map_or(safe_block + 1, |block| block + 1);
+            if closing_from <= latest_block {
+                let filter = lifecycle::filter(self.proof_marketplace_http.address())
+                    .from_block(
+                        closing_from.max(latest_block.saturating_sub(blocks_at_once.into())),
+                    )
+                    .to_block(latest_block);
+                for log in provider_http.provider().get_logs(&filter).await? {
+                    processor::handle_marketplace_log(log, ask_context).await?;
+                }
+                closing_scanned = Some(latest_block);
+            }
+
+            if start_block > safe_block {
+                // to reduce calls on eth_latestBlock
+                tokio::time::sleep(Duration::from_millis(2000)).await;
+                continue;
+            }
+
+            let end = if start_block + blocks_at_once > safe_block {
+                safe_block
+            } else {
+                start_block + blocks_at_once - 1
+            };
 
-        if thread_count.load(Ordering::SeqCst) >= max_thread_count {
-            thread::sleep(Duration::from_millis(60));
-            log::warn!(
-                "Stopped proof generation as {} proof generations in progress",
-                max_thread_count
+            log::info!(
+                "Searching for TASKs on {} from Block {} to {}...",
+                self.name,
+                start_block,
+                end
             );
-            continue;
-        }
 
-        let latest_block = provider_http.get_block_number().await.unwrap();
+            let filter = ask_index::marketplace_filter(&self.proof_marketplace_http)
+                .from_block(start_block)
+                .to_block(end);
+
+            let logs = provider_http.provider().get_logs(&filter).await?;
+            self.process_confirmed(ask_context, logs, end).await?;
+            start_block = end + 1;
+
+            let caught_up = start_block > safe_block;
+            if let (IngestionMode::Subscription, Some(ws_url), true) =
+                (ingestion_mode, ws_url, caught_up)
+            {
+                if Instant::now() >= subscription_retry_at {
+                    start_block = self
+                        .run_subscription(
+                            ws_url.clone(),
+                            start_block,
+                            confirmations,
+                            ask_context,
+                            should_stop,
+                        )
+                        .await?;
+                    // back-fill whatever was missed while the subscription was down before retrying
+                    log::warn!(
+                        "Falling back to polling {} from block {}",
+                        self.name,
+                        start_block
+                    );
+                    subscription_retry_at = Instant::now() + SUBSCRIPTION_RETRY_INTERVAL;
+                }
+            }
+        }
+        Ok(())
+    }
 
-        let end = if start_block + blocks_at_once > latest_block {
-            thread::sleep(Duration::from_millis(2000)); // to reduce calls on eth_latestBlock
-            latest_block - 1
-        } else {
-            start_block + blocks_at_once - 1
+    // Rolls the journal back if blocks it processed were reorged out, returns the block to
+    // re-process from
+    async fn check_reorg(&self, journal: &Journal) -> Result<Option<U64>, Box<dyn Error>> {
+        let Some(fork_block) = reorg::find_fork_point(self.client_http.as_ref(), journal).await?
+        else {
+            return Ok(None);
         };
-
-        log::info!(
-            "Searching for TASKs from Block {} to {}...",
-            start_block,
-            end
+        let rolled_back_asks = journal.rollback_to(fork_block)?;
+        log::warn!(
+            "Chain reorg detected on {}, re-processing from block {}. Rolled back asks: {:?}",
+            self.name,
+            fork_block + 1,
+            rolled_back_asks
         );
+        Ok(Some(fork_block + 1))
+    }
 
-        let filter = proof_marketplace_http
-            .task_created_filter()
-            .filter
-            .from_block(start_block)
-            .to_block(end);
+    // Handles the logs of confirmed blocks up to `end` and checkpoints `end` in the journal
+    async fn process_confirmed(
+        &self,
+        ask_context: &Arc<AskContext>,
+        logs: Vec<Log>,
+        end: U64,
+    ) -> Result<(), Box<dyn Error>> {
+        for log in logs {
+            processor::handle_marketplace_log(log, ask_context).await?;
+        }
+        if let Some(end_hash) = self.client_http.get_block(end).await?.and_then(|b| b.hash) {
+            ask_context.journal.record_block_hash(end, end_hash)?;
+        }
+        ask_context.journal.set_last_processed_block(end)?;
+        Ok(())
+    }
 
-        let logs = client_http.provider().get_logs(&filter).await?;
+    // Follows the marketplace logs over websocket until the subscription drops or a stop is
+    // requested. Logs from `start_block` to the first head are back-filled over HTTP, and logs
+    // are processed once `confirmations` deep, like when polling. Logs that close asks are
+    // handled right away. Returns the block from which polling should resume.
+    async fn run_subscription(
+        &self,
+        ws_url: String,
+        mut start_block: U64,
+        confirmations: u64,
+        ask_context: &Arc<AskContext>,
+        should_stop: &AtomicBool,
+    ) -> Result<U64, Box<dyn Error>> {
+        let filter = ask_index::marketplace_filter(&self.proof_marketplace_http);
+        let mut receiver =
+            subscription::spawn_subscription(ws_url, filter.clone(), SUBSCRIPTION_HEAD_TIMEOUT);
+        let mut pending = subscription::PendingLogs::default();
+        let mut back_filled = false;
+        while !should_stop.load(Ordering::Acquire) {
+            let event = match tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(_) => continue,
+            };
 
-        for log in logs {
-            let event = proof_marketplace_http.decode_event::<pmp::TaskCreatedFilter>(
-                "TaskCreated",
-                log.topics,
-                log.data,
-            )?;
-            let generator = match key_store.get_generator(&event.generator) {
-                Some(gen) => {
-                    let ask_details: &(pmp::Ask, u8, H160, H160) =
-                        &proof_marketplace_http.list_of_ask(event.ask_id).await?;
-
-                    log::debug!("Generator Data (on polling): {:?}", &gen);
-                    if gen.supported_market_ids.contains(&ask_details.0.market_id) {
-                        gen
-                    } else {
-                        log::debug!("Skipping ask: {:?}, because Generator: {:?} doesn't support Market: {:?}", event.ask_id, gen.address, ask_details.0.market_id);
-                        continue;
+            let head = match event {
+                subscription::SubscriptionEvent::Log(log) => {
+                    if lifecycle::closed_ask(&log).is_some() {
+                        processor::handle_marketplace_log((*log).clone(), ask_context).await?;
                     }
-                }
-                None => {
-                    log::debug!(
-                        "Skipping ask: {:?}, because it is not assigned to my generators",
-                        event.ask_id
-                    );
+                    pending.insert(*log);
                     continue;
                 }
+                subscription::SubscriptionEvent::NewHead(block_number) => block_number,
             };
-
-            let ask_state = &proof_marketplace_http.get_ask_state(event.ask_id).await?;
-            dbg!(&ask_state);
-            let ask_state = ask::get_ask_state(*ask_state);
-            dbg!(ask_state);
-            if ask_state == ask::AskState::Assigned {
-                log::info!(
-                    "Need to generate proof (polling) for ASK ID : {}",
-                    event.ask_id
-                );
-                let gen_ecies_private_key = generator.ecies_priv_key.serialize();
-
-                let proof_market_place_clone_http = Arc::clone(&proof_marketplace_http);
-                let submitter_pmp_clone_http = Arc::clone(&submitter_pmp);
-                let markets_clone = Arc::clone(&markets);
-                // code inside thread starts here
-
-                thread_count.fetch_add(1, Ordering::SeqCst);
-                let thread_count_clone = Arc::clone(&thread_count);
-
-                tokio::spawn(async move {
-                    log::warn!("Spin up new thread from proof generation calls");
-                    let generate_proof_args = GenerateProofParams {
-                        ask_id: event.ask_id,
-                        new_acl: event.new_acl,
-                        proof_market_place_contract_http: proof_market_place_clone_http,
-                        ecies_private_key: &gen_ecies_private_key,
-                        start_block: &runtime_start_block,
-                        end_block: &latest_block,
-                        markets: &markets_clone,
-                    };
-
-                    let proof = match listener::generate_proof(generate_proof_args).await {
-                        Ok(proof) => proof,
-                        Err(err) => return log::error!("{}", err),
-                    };
-
-                    log::info!("{:?}", &proof);
-
-                    let proof_transaction = match proof {
-                        listener::Proof::ValidProof(proof) => {
-                            log::info!("Submitting proof on-chain...");
-                            submitter_pmp_clone_http
-                                .lock()
-                                .await
-                                .submit_proof(event.ask_id, proof)
-                                .send()
-                                .await
-                                .unwrap()
-                                .await
-                                .unwrap()
-                        }
-                        listener::Proof::InvalidProof(invalid_proof_signature) => {
-                            log::info!("Submitting signature on-chain...");
-                            submitter_pmp_clone_http
-                                .lock()
-                                .await
-                                .submit_proof_for_invalid_inputs(
-                                    event.ask_id,
-                                    invalid_proof_signature,
-                                )
-                                .send()
-                                .await
-                                .unwrap()
-                                .await
-                                .unwrap()
-                        }
-                    };
-
-                    match proof_transaction {
-                        Some(tx_data) => {
-                            log::info!(
-                                "Submitted proof for OLD ask with id : {} via transaction {:?}",
-                                event.ask_id,
-                                tx_data.transaction_hash
-                            );
-                        }
-                        None => {
-                            log::error!("Error in submitting proof for ASK ID : {}", event.ask_id);
-                        }
+            ask_context.set_current_block(head);
+
+            // logs of the blocks between the last poll and the subscription's first head
+            if !back_filled {
+                if start_block <= head {
+                    let filter = filter.clone().from_block(start_block).to_block(head);
+                    for log in self.client_http.provider().get_logs(&filter).await? {
+                        pending.insert(log);
                     }
+                }
+                back_filled = true;
+            }
+
+            if let Some(resume_block) = self.check_reorg(&ask_context.journal).await? {
+                // polling re-processes the reorged blocks before subscribing again
+                return Ok(resume_block);
+            }
 
-                    thread_count_clone.fetch_sub(1, Ordering::SeqCst);
-                    // code inside thread ends here
-                });
+            let safe_block = head.saturating_sub(confirmations.max(1).into());
+            if safe_block >= start_block {
+                let logs = pending.take_until(safe_block);
+                self.process_confirmed(ask_context, logs, safe_block)
+                    .await?;
+                start_block = safe_block + 1;
             }
+            metrics::BLOCK_LAG
+                .with_label_values(&[&self.name])
+                .set((head + 1).saturating_sub(start_block).as_u64() as i64);
         }
 
-        start_block = end + 1;
+        Ok(start_block)
     }
+}
 
+// Re-drives asks that were in flight when the listener last stopped
+async fn resume_unfinished_asks(ask_context: &AskContext) -> Result<(), Box<dyn Error>> {
+    let proof_marketplace_http = &ask_context.proof_marketplace_http;
+    for record in ask_context.journal.unfinished_asks()? {
+        let generator = match ask_context.generator(&record.generator) {
+            Some(gen) => gen,
+            None => {
+                log::warn!(
+                    "Not resuming ask {}, generator {:?} is no longer configured",
+                    record.ask_id,
+                    record.generator
+                );
+                continue;
+            }
+        };
+        log::info!(
+            "Resuming ask {} on {} from stage {:?}",
+            record.ask_id,
+            ask_context.chain_name,
+            record.stage
+        );
+        let ask_details = proof_marketplace_http.list_of_ask(record.ask_id).await?;
+        let stake = ask_context
+            .acceptance
+            .stake_required(proof_marketplace_http, ask_details.0.market_id)
+            .await?;
+        processor::schedule_ask(
+            ask_context,
+            record,
+            generator.ecies_priv_key.serialize(),
+            &ask_details.0,
+            stake,
+        );
+    }
     Ok(())
 }
+
+// The config file at `path`, or in the generator_config directory next to or above the working
+// directory
+fn config_path(path: Option<&str>, file_name: &str) -> String {
+    match path {
+        Some(path) => path.to_string(),
+        None => {
+            let local = format!("./generator_config/{}", file_name);
+            if fs::metadata(&local).is_ok() {
+                local
+            } else {
+                format!("../generator_config/{}", file_name)
+            }
+        }
+    }
+}
+
+fn read_config(path: Option<&str>, file_name: &str) -> Result<String, Box<dyn Error>> {
+    Ok(fs::read_to_string(config_path(path, file_name))?)
+}
+
+// Loads the generator's ECIES key and checks that it can decrypt what its public key encrypts
+fn load_generator(config: GeneratorConfigModel) -> Result<generator_store::Generator, String> {
+    let generator_address = Address::from_str(&config.address)
+        .map_err(|_| "Invalid Address for generator provided".to_string())?;
+
+    let mut original_message = vec![0; 32]; // for example, 32 bytes
+    rand_bytes(&mut original_message).expect("Failed to generate random bytes");
+
+    let private_key = key_provider::load_private_key(
+        config.ecies_private_key.as_ref(),
+        config.ecies_key.as_ref(),
+    )
+    .map_err(|err| {
+        format!(
+            "Unable to load the ECIES key of generator {:?}: {}",
+            generator_address, err
+        )
+    })?;
+    let ecies_secret_key = ecies::SecretKey::parse_slice(&private_key)
+        .map_err(|_| "Invalid ECIES private key for generator provided".to_string())?;
+
+    let public_key = ecies::PublicKey::from_secret_key(&ecies_secret_key).serialize();
+    let encrypted_message = ecies::encrypt(&public_key, &original_message)
+        .map_err(|_| "Unable to encrypt message using public key".to_string())?;
+    let decrypted_message = ecies::decrypt(&private_key, &encrypted_message)
+        .map_err(|_| "Unable to decrypt message using private key".to_string())?;
+    if original_message != decrypted_message {
+        return Err("The public and private keys do not match!".into());
+    }
+
+    let mut supported_markets: Vec<U256> = vec![];
+    for market in config.supported_markets.into_iter() {
+        let market_temp = U256::from_dec_str(&market)
+            .map_err(|_| format!("Invalid market id {} for generator", market))?;
+        supported_markets.push(market_temp);
+    }
+
+    Ok(generator_store::Generator {
+        address: generator_address,
+        supported_market_ids: supported_markets,
+        ecies_priv_key: ecies_secret_key,
+        ecies_pub_key: ecies::PublicKey::from_secret_key(&ecies_secret_key),
+        min_reward: config.min_reward,
+        staked_amount: config.staked_amount,
+    })
+}
+
+fn market_concurrency(
+    markets: &HashMap<String, MarketDetails>,
+) -> Result<HashMap<U256, usize>, Box<dyn Error>> {
+    let mut market_concurrency = HashMap::new();
+    for (market_id, market) in markets.iter() {
+        if let Some(max_concurrency) = market.max_concurrency {
+            market_concurrency.insert(U256::from_dec_str(market_id)?, max_concurrency);
+        }
+    }
+    Ok(market_concurrency)
+}
+
+// Secrets of the generators from the environment take precedence over the config files
+fn apply_env_overrides(config: &mut Config) {
+    for generator in config.generator_config.iter_mut() {
+        let variable = format!(
+            "LISTENER_ECIES_PRIVATE_KEY_{}",
+            generator.address.trim_start_matches("0x").to_uppercase()
+        );
+        if let Ok(ecies_private_key) = std::env::var(variable) {
+            generator.ecies_private_key = Some(Secret::new(ecies_private_key));
+        }
+    }
+}
+
+// Same for the key and endpoints of a chain configured at the top level, chains listed in
+// `chains` take their key from a `signer` source instead
+fn apply_chain_env_overrides(chain: &mut ChainConfig) {
+    if let Ok(private_key) = std::env::var("LISTENER_PRIVATE_KEY") {
+        chain.private_key = Some(Secret::new(private_key));
+    }
+    if let Ok(http_url) = std::env::var("LISTENER_HTTP_URL") {
+        chain.http_url = http_url;
+    }
+    if let Ok(ws_url) = std::env::var("LISTENER_WS_URL") {
+        chain.ws_url = Some(ws_url);
+    }
+}
+
+// The chains listed in `chains`, or else the one configured at the top level of
+// runtime_config.json. Every chain needs a journal of its own.
+fn chain_configs(
+    runtime_config: &str,
+    mut chains: Vec<ChainConfig>,
+) -> Result<Vec<ChainConfig>, Box<dyn Error>> {
+    if chains.is_empty() {
+        let mut chain = serde_json::from_str::<SingleChainConfig>(runtime_config)
+            .map_err(|err| {
+                format!(
+                    "No chain configured in `chains` or at the top level: {}",
+                    err
+                )
+            })?
+            .runtime_config;
+        apply_chain_env_overrides(&mut chain);
+        return Ok(vec![chain]);
+    }
+
+    let mut names = HashSet::new();
+    let mut journal_paths = HashSet::new();
+    for chain in chains.iter_mut() {
+        if !names.insert(chain.name()) {
+            return Err(format!("{} is configured twice", chain.name()).into());
+        }
+        let journal_path = chain
+            .journal_path
+            .get_or_insert_with(|| format!("{}_{}", DEFAULT_JOURNAL_PATH, chain.chain_id));
+        if !journal_paths.insert(journal_path.clone()) {
+            return Err(format!("Journal {} is used by several chains", journal_path).into());
+        }
+    }
+    Ok(chains)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_chains() {
+        let chain = |name: &str, chain_id: u64| {
+            format!(
+                r#"{{"name": "{}", "http_url": "http://rpc", "proof_market_place": "0x01",
+                "generator_registry": "0x02", "start_block": 1, "chain_id": {}}}"#,
+                name, chain_id
+            )
+        };
+        let runtime_config = |fields: &str| {
+            format!(
+                r#"{{"runtime_config": {{{} "params_path": "", "markets": {{}}}}}}"#,
+                fields
+            )
+        };
+        let resolve = |file: String| {
+            let config: RuntimeConfig = serde_json::from_str(&file).unwrap();
+            chain_configs(&file, config.runtime_config.chains)
+        };
+
+        // a single chain at the top level, as before
+        let top_level = chain("arbitrum", 421614);
+        let chains = resolve(runtime_config(&format!(
+            "{},",
+            &top_level[1..top_level.len() - 1]
+        )))
+        .unwrap();
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].chain_id, 421614);
+        assert_eq!(chains[0].journal_path, None);
+
+        let chains = resolve(runtime_config(&format!(
+            r#""chains": [{}, {}],"#,
+            chain("arbitrum", 421614),
+            chain("other", 1)
+        )))
+        .unwrap();
+        let journal_paths: Vec<_> = chains
+            .iter()
+            .map(|chain| chain.journal_path.clone().unwrap())
+            .collect();
+        assert_eq!(
+            journal_paths,
+            vec!["./listener_journal_421614", "./listener_journal_1"]
+        );
+
+        assert!(resolve(runtime_config(&format!(
+            r#""chains": [{}, {}],"#,
+            chain("arbitrum", 421614),
+            chain("arbitrum", 1)
+        )))
+        .is_err());
+        assert!(resolve(runtime_config("")).is_err());
+    }
+}
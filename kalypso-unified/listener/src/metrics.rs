@@ -0,0 +1,126 @@
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, Encoder, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+lazy_static! {
+    pub static ref ASKS_SEEN: IntCounter =
+        register_int_counter!("listener_asks_seen_total", "TaskCreated events processed").unwrap();
+    pub static ref ASKS_SKIPPED: IntCounterVec = register_int_counter_vec!(
+        "listener_asks_skipped_total",
+        "Asks not proven by this listener, by reason",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref PROOFS_GENERATED: IntCounterVec = register_int_counter_vec!(
+        "listener_proofs_generated_total",
+        "Proofs generated, by kind (valid proof or signature over invalid inputs)",
+        &["kind"]
+    )
+    .unwrap();
+    pub static ref PIPELINE_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "listener_pipeline_errors_total",
+        "Errors while proving or submitting asks, by kind",
+        &["kind"]
+    )
+    .unwrap();
+    pub static ref ASKS_CLOSED: IntCounterVec = register_int_counter_vec!(
+        "listener_asks_closed_total",
+        "Asks closed on-chain while this listener was proving them, by reason",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref POLICY_VIOLATIONS: IntCounterVec = register_int_counter_vec!(
+        "listener_policy_violations_total",
+        "Assigned asks breaking an acceptance rule, by rule",
+        &["rule"]
+    )
+    .unwrap();
+    pub static ref GENERATOR_LATENCY: HistogramVec = register_histogram_vec!(
+        "listener_generator_latency_seconds",
+        "Time from forwarding an ask until its proof is ready, by market",
+        &["market_id"],
+        vec![1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 3600.0]
+    )
+    .unwrap();
+    pub static ref SECRET_FETCH_TIME: Histogram = register_histogram!(
+        "listener_ask_secret_fetch_seconds",
+        "Time spent fetching and decrypting the secret inputs of an ask"
+    )
+    .unwrap();
+    pub static ref ASK_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "listener_ask_lookups_total",
+        "AskCreated lookups, by source (cache or rpc search)",
+        &["source"]
+    )
+    .unwrap();
+    pub static ref SUBMISSION_GAS_USED: Histogram = register_histogram!(
+        "listener_submission_gas_used",
+        "Gas used by mined proof submission transactions",
+        exponential_buckets(50_000.0, 2.0, 8).unwrap()
+    )
+    .unwrap();
+    pub static ref BLOCK_LAG: IntGaugeVec = register_int_gauge_vec!(
+        "listener_block_lag",
+        "Blocks between the chain head and the last processed block, by chain",
+        &["chain"]
+    )
+    .unwrap();
+    pub static ref IN_FLIGHT_JOBS: IntGauge = register_int_gauge!(
+        "listener_in_flight_jobs",
+        "Asks currently being proven or submitted"
+    )
+    .unwrap();
+}
+
+pub fn skip_ask(reason: &str) {
+    ASKS_SKIPPED.with_label_values(&[reason]).inc();
+}
+
+/// Serves the metrics in the prometheus text format on every path of `address`.
+pub async fn serve(address: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(address).await?;
+    log::info!("Serving metrics on http://{}/metrics", address);
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                log::error!("Failed to accept metrics connection: {}", err);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let connection = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service_fn(render));
+            if let Err(err) = connection.await {
+                log::debug!("Metrics connection closed: {}", err);
+            }
+        });
+    }
+}
+
+async fn render(
+    _request: Request<hyper::body::Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        log::error!("Failed to encode metrics: {}", err);
+    }
+    let mut response = Response::new(Full::new(Bytes::from(buffer)));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    Ok(response)
+}
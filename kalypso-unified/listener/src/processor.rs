@@ -0,0 +1,710 @@
+use crate::acceptance::{AcceptanceMode, AcceptancePolicy, AskCandidate, MarketRules};
+use crate::ask;
+use crate::ask_index;
+use crate::error::{Action, PipelineError};
+use crate::generator_backend::GeneratorBackend;
+use crate::generator_store::{Generator, GeneratorStore};
+use crate::journal::{AskRecord, AskStage, Journal};
+use crate::key_provider::ListenerSigner;
+use crate::lifecycle::{self, AskClosed, Watchlist};
+use crate::listener::{self, GenerateProofParams};
+use crate::metrics;
+use crate::scheduler::{Dispatch, Job, Scheduler};
+use crate::submitter::{SubmissionOutcome, Submitter};
+use crate::MarketDetails;
+use bindings::proof_marketplace as pmp;
+use ethers::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+// attempts of errors whose policy is to retry, e.g. RPC or generator outages
+const MAX_GENERATION_ATTEMPTS: u32 = 3;
+const GENERATION_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+pub type ProofMarketPlaceContractHttp =
+    pmp::ProofMarketplace<SignerMiddleware<Provider<Http>, ListenerSigner>>;
+
+/// Market settings and their generator backends, swapped as a whole on config reloads.
+pub struct Markets {
+    pub details: HashMap<String, MarketDetails>,
+    pub generators: HashMap<String, Arc<dyn GeneratorBackend>>,
+}
+
+/// Shared by the chains the listener serves: the generators, their backends and the proof budget.
+pub struct Shared {
+    pub key_store: RwLock<GeneratorStore>,
+    pub markets: RwLock<Arc<Markets>>,
+    pub scheduler: Scheduler,
+    // set on shutdown, queued asks are no longer dispatched
+    pub draining: AtomicBool,
+    // asks being proven or submitted right now
+    pub running_jobs: AtomicUsize,
+}
+
+// One per chain, shared by every spawned proof task of the chain
+pub struct AskContext {
+    pub shared: Arc<Shared>,
+    // index of the chain in the scheduler and its name in logs
+    pub chain: usize,
+    pub chain_name: String,
+    pub proof_marketplace_http: Arc<ProofMarketPlaceContractHttp>,
+    pub submitter: Submitter,
+    pub journal: Journal,
+    pub start_block: U64,
+    // asks queued or running, so a re-processed event doesn't schedule a duplicate
+    pub in_flight: std::sync::Mutex<HashSet<U256>>,
+    // prove asks but never submit them
+    pub dry_run: bool,
+    // generator and market pairs that failed the on-chain checks in refuse mode
+    pub refused_markets: RwLock<HashSet<(Address, U256)>>,
+    pub acceptance: AcceptancePolicy,
+    // running asks that are called off when they get closed on-chain
+    pub watchlist: Watchlist,
+}
+
+impl AskContext {
+    /// The current market config, proof tasks keep using the one they started with.
+    pub fn markets(&self) -> Arc<Markets> {
+        Arc::clone(&self.shared.markets.read().unwrap())
+    }
+
+    pub fn generator(&self, address: &Address) -> Option<Generator> {
+        self.shared
+            .key_store
+            .read()
+            .unwrap()
+            .get_generator(address)
+            .cloned()
+    }
+
+    pub fn current_block(&self) -> U64 {
+        self.shared.scheduler.current_block(self.chain)
+    }
+
+    pub fn set_current_block(&self, block: U64) {
+        self.shared.scheduler.set_current_block(self.chain, block)
+    }
+}
+
+/// Indexes AskCreated logs, calls off running asks that got closed and handles TaskCreated logs
+/// of the marketplace filter.
+pub async fn handle_marketplace_log(
+    log: Log,
+    ask_context: &Arc<AskContext>,
+) -> Result<(), Box<dyn Error>> {
+    if ask_index::is_ask_created(&log) {
+        ask_index::index_ask_created(
+            &ask_context.journal,
+            &ask_context.proof_marketplace_http,
+            log,
+        )
+    } else if let Some((ask_id, reason)) = lifecycle::closed_ask(&log) {
+        // a retracted close is of no use, the ask was called off already or is still assigned
+        if log.removed != Some(true) && ask_context.watchlist.close(&ask_id, reason) {
+            log::info!("Ask {} was closed on-chain ({})", ask_id, reason.label());
+        }
+        Ok(())
+    } else {
+        handle_task_created_log(log, ask_context).await
+    }
+}
+
+async fn handle_task_created_log(
+    log: Log,
+    ask_context: &Arc<AskContext>,
+) -> Result<(), Box<dyn Error>> {
+    let proof_marketplace_http = &ask_context.proof_marketplace_http;
+    let event = proof_marketplace_http.decode_event::<pmp::TaskCreatedFilter>(
+        "TaskCreated",
+        log.topics,
+        log.data,
+    )?;
+
+    // a subscribed log can be retracted when its block gets reorged out
+    if log.removed == Some(true) {
+        if ask_context.journal.remove_unsubmitted_ask(event.ask_id)? {
+            log::warn!(
+                "TaskCreated for ask {} was reorged out, rolled it back",
+                event.ask_id
+            );
+        }
+        return Ok(());
+    }
+    metrics::ASKS_SEEN.inc();
+    let (generator, ask_details) = match ask_context.generator(&event.generator) {
+        Some(gen) => {
+            let ask_details: (pmp::Ask, u8, H160, H160) =
+                proof_marketplace_http.list_of_ask(event.ask_id).await?;
+
+            log::debug!("Generator Data (on polling): {:?}", &gen);
+            if !gen.supported_market_ids.contains(&ask_details.0.market_id) {
+                log::debug!(
+                    "Skipping ask: {:?}, because Generator: {:?} doesn't support Market: {:?}",
+                    event.ask_id,
+                    gen.address,
+                    ask_details.0.market_id
+                );
+                metrics::skip_ask("unsupported_market");
+                return Ok(());
+            }
+            if ask_context
+                .refused_markets
+                .read()
+                .unwrap()
+                .contains(&(gen.address, ask_details.0.market_id))
+            {
+                log::warn!(
+                    "Skipping ask: {:?}, Generator: {:?} failed the on-chain checks for Market: {:?}",
+                    event.ask_id,
+                    gen.address,
+                    ask_details.0.market_id
+                );
+                metrics::skip_ask("failed_checks");
+                return Ok(());
+            }
+            (gen, ask_details.0)
+        }
+        None => {
+            log::debug!(
+                "Skipping ask: {:?}, because it is not assigned to my generators",
+                event.ask_id
+            );
+            metrics::skip_ask("other_generator");
+            return Ok(());
+        }
+    };
+
+    let ask_state = &proof_marketplace_http.get_ask_state(event.ask_id).await?;
+    let ask_state = ask::get_ask_state(*ask_state);
+    log::debug!("Ask {} state: {:?}", event.ask_id, ask_state);
+    if ask_state == ask::AskState::Assigned {
+        let record = AskRecord::new(
+            event.ask_id,
+            event.generator,
+            event.new_acl,
+            log.block_number,
+        );
+        if !ask_context.journal.record_seen(&record)? {
+            log::debug!("Ask {} is already journaled, skipping", event.ask_id);
+            metrics::skip_ask("already_seen");
+            return Ok(());
+        }
+
+        let stake = ask_context
+            .acceptance
+            .stake_required(proof_marketplace_http, ask_details.market_id)
+            .await?;
+        if !accept(ask_context, &generator, &ask_details, event.ask_id, stake) {
+            return Ok(());
+        }
+
+        log::info!("Need to generate proof for ASK ID : {}", event.ask_id);
+
+        schedule_ask(
+            ask_context,
+            record,
+            generator.ecies_priv_key.serialize(),
+            &ask_details,
+            stake,
+        );
+    } else {
+        metrics::skip_ask("not_assigned");
+    }
+
+    Ok(())
+}
+
+// Checks the ask against the acceptance policy. Asks breaking it are dropped in skip mode.
+fn accept(
+    ask_context: &AskContext,
+    generator: &Generator,
+    ask: &pmp::Ask,
+    ask_id: U256,
+    stake: U256,
+) -> bool {
+    let policy = &ask_context.acceptance;
+    let markets = ask_context.markets();
+    let no_rules = MarketRules::default();
+    let market = markets
+        .details
+        .get(&ask.market_id.to_string())
+        .map_or(&no_rules, |details| &details.acceptance);
+    let candidate = AskCandidate {
+        ask_id,
+        ask,
+        generator,
+        market,
+        stake_required: stake,
+        locked_stake: policy.locked_stake(&generator.address),
+    };
+    let violations = policy.violations(&candidate);
+    if violations.is_empty() {
+        return true;
+    }
+    let reasons: Vec<String> = violations
+        .iter()
+        .map(|(rule, reason)| format!("{}: {}", rule, reason))
+        .collect();
+    if policy.mode == AcceptanceMode::Flag {
+        log::warn!(
+            "Ask {} breaks the policy of generator {:?}, proving it anyway: {}",
+            candidate.ask_id,
+            generator.address,
+            reasons.join(", ")
+        );
+        return true;
+    }
+    log::warn!(
+        "Skipping ask {}, it breaks the policy of generator {:?}: {}",
+        candidate.ask_id,
+        generator.address,
+        reasons.join(", ")
+    );
+    metrics::skip_ask("policy");
+    if let Err(err) = ask_context
+        .journal
+        .record_dropped(ask_id, format!("policy: {}", reasons.join(", ")))
+    {
+        log::error!("Failed to update journal for ask {}: {}", ask_id, err);
+    }
+    false
+}
+
+/// Queues the ask for proof generation, refusing it if its deadline can't be met. `stake` is
+/// locked from the generator's stake until the ask is done.
+pub fn schedule_ask(
+    ask_context: &AskContext,
+    record: AskRecord,
+    ecies_private_key: [u8; 32],
+    ask: &pmp::Ask,
+    stake: U256,
+) {
+    let ask_id = record.ask_id;
+    if !ask_context.in_flight.lock().unwrap().insert(ask_id) {
+        log::debug!("Ask {} is already being processed", ask_id);
+        return;
+    }
+    ask_context.acceptance.lock(ask_id, record.generator, stake);
+
+    let job = Job {
+        chain: ask_context.chain,
+        record,
+        ecies_private_key,
+        market_id: ask.market_id,
+        reward: ask.reward,
+        deadline: ask.deadline,
+    };
+    if let Err(job) = ask_context.shared.scheduler.enqueue(job) {
+        abandon(
+            ask_context,
+            &job,
+            "refused, it can't be proven before its deadline",
+        );
+    }
+}
+
+/// Hands queued asks of every chain to proof tasks as slots free up, in deadline order.
+/// `chains` are indexed by [`Job::chain`].
+pub async fn run_dispatcher(shared: Arc<Shared>, chains: Vec<Arc<AskContext>>) {
+    loop {
+        // queued asks stay in the journal and are resumed on the next start
+        if !shared.draining.load(Ordering::Acquire) {
+            while let Some(dispatch) = shared.scheduler.next() {
+                let ask_context = match &dispatch {
+                    Dispatch::Run(job, _) | Dispatch::Abandon(job) => &chains[job.chain],
+                };
+                match dispatch {
+                    Dispatch::Run(job, time_left) => {
+                        // counted before the task starts so a drain can't miss it, JobSlot
+                        // takes it off again
+                        shared.running_jobs.fetch_add(1, Ordering::AcqRel);
+                        let ask_context = Arc::clone(ask_context);
+                        tokio::spawn(async move {
+                            log::warn!("Spin up new thread from proof generation calls");
+                            let _slot =
+                                JobSlot::new(&ask_context, job.record.ask_id, job.market_id);
+                            drive_ask(&ask_context, job, time_left).await;
+                        });
+                    }
+                    Dispatch::Abandon(job) => abandon(
+                        ask_context,
+                        &job,
+                        "abandoned, it can no longer be proven before its deadline",
+                    ),
+                }
+            }
+        }
+        // wake up periodically as well, queued asks may run out of time
+        let _ = tokio::time::timeout(Duration::from_secs(1), shared.scheduler.wait()).await;
+    }
+}
+
+// Holds the scheduler slot of a running ask and frees it even if the proof task panics
+struct JobSlot<'a> {
+    ask_context: &'a AskContext,
+    ask_id: U256,
+    market_id: U256,
+}
+
+impl<'a> JobSlot<'a> {
+    fn new(ask_context: &'a AskContext, ask_id: U256, market_id: U256) -> Self {
+        metrics::IN_FLIGHT_JOBS.inc();
+        Self {
+            ask_context,
+            ask_id,
+            market_id,
+        }
+    }
+}
+
+impl Drop for JobSlot<'_> {
+    fn drop(&mut self) {
+        metrics::IN_FLIGHT_JOBS.dec();
+        if let Ok(mut in_flight) = self.ask_context.in_flight.lock() {
+            in_flight.remove(&self.ask_id);
+        }
+        self.ask_context.acceptance.release(&self.ask_id);
+        self.ask_context.watchlist.unwatch(&self.ask_id);
+        let shared = &self.ask_context.shared;
+        shared.running_jobs.fetch_sub(1, Ordering::AcqRel);
+        shared.scheduler.finish(&self.market_id);
+    }
+}
+
+fn abandon(ask_context: &AskContext, job: &Job, reason: &str) {
+    let ask_id = job.record.ask_id;
+    metrics::skip_ask("deadline");
+    log::warn!(
+        "Ask {} {} (deadline block {}, current block {})",
+        ask_id,
+        reason,
+        job.deadline,
+        ask_context.current_block()
+    );
+    if let Err(err) = ask_context
+        .journal
+        .record_dropped(ask_id, reason.to_string())
+    {
+        log::error!("Failed to update journal for ask {}: {}", ask_id, err);
+    }
+    ask_context.in_flight.lock().unwrap().remove(&ask_id);
+    ask_context.acceptance.release(&ask_id);
+}
+
+// Moves an ask through its remaining lifecycle stages, journaling each step so it can be resumed
+async fn drive_ask(ask_context: &AskContext, job: Job, time_left: Option<Duration>) {
+    let Job {
+        record,
+        ecies_private_key,
+        market_id,
+        ..
+    } = job;
+    let ask_id = record.ask_id;
+    let journal = &ask_context.journal;
+    // watched before the state check so a close in between isn't missed
+    let mut closed = ask_context.watchlist.watch(ask_id);
+
+    let ask_state = match ask_context
+        .proof_marketplace_http
+        .get_ask_state(ask_id)
+        .await
+    {
+        Ok(state) => ask::get_ask_state(state),
+        Err(err) => return log::error!("Failed fetching state of ask {}: {}", ask_id, err),
+    };
+    if ask_state != ask::AskState::Assigned {
+        let stage = if ask_state == ask::AskState::Complete && record.stage == AskStage::Submitted {
+            AskStage::Confirmed
+        } else {
+            AskStage::Dropped
+        };
+        log::info!(
+            "Ask {} is no longer assigned ({:?}), marking it {:?}",
+            ask_id,
+            ask_state,
+            stage
+        );
+        if let Err(err) = journal.update_stage(ask_id, stage) {
+            log::error!("Failed to update journal for ask {}: {}", ask_id, err);
+        }
+        return;
+    }
+
+    let proof = match record.proof.clone() {
+        Some(proof) => proof,
+        None => {
+            if let Err(err) = journal.update_stage(ask_id, AskStage::ForwardedToGenerator) {
+                log::error!("Failed to update journal for ask {}: {}", ask_id, err);
+            }
+            let generation_timer = Instant::now();
+            let deadline = time_left.map(|time_left| generation_timer + time_left);
+            let generation = generate(ask_context, &record, &ecies_private_key, deadline);
+            // None if the deadline passed first
+            let generation = async {
+                match time_left {
+                    Some(time_left) => tokio::time::timeout(time_left, generation).await.ok(),
+                    None => Some(generation.await),
+                }
+            };
+            let proof = tokio::select! {
+                proof = generation => proof,
+                reason = lifecycle::closed(&mut closed) => {
+                    return call_off(ask_context, ask_id, market_id, reason, true).await;
+                }
+            };
+            let proof = match proof {
+                Some(proof) => proof,
+                None => {
+                    ask_context
+                        .shared
+                        .scheduler
+                        .record_latency(market_id, generation_timer.elapsed());
+                    // the generator would keep proving an ask nobody can submit anymore
+                    cancel_generation(ask_context, ask_id, market_id).await;
+                    if let Err(err) = journal.record_dropped(
+                        ask_id,
+                        "proof generation did not finish before the deadline".to_string(),
+                    ) {
+                        log::error!("Failed to update journal for ask {}: {}", ask_id, err);
+                    }
+                    return log::error!("Ask {} crossed its deadline while proving", ask_id);
+                }
+            };
+            let proof = match proof {
+                Ok(proof) => proof,
+                Err(err) => {
+                    log::error!("Abandoning ask {}: {}", ask_id, err);
+                    if let Err(err) = journal.record_dropped(ask_id, err.to_string()) {
+                        log::error!("Failed to update journal for ask {}: {}", ask_id, err);
+                    }
+                    return;
+                }
+            };
+            ask_context
+                .shared
+                .scheduler
+                .record_latency(market_id, generation_timer.elapsed());
+            metrics::GENERATOR_LATENCY
+                .with_label_values(&[&market_id.to_string()])
+                .observe(generation_timer.elapsed().as_secs_f64());
+            let kind = match &proof {
+                listener::Proof::ValidProof(_) => "valid",
+                listener::Proof::InvalidProof(_) => "invalid",
+            };
+            metrics::PROOFS_GENERATED.with_label_values(&[kind]).inc();
+            if let Err(err) = journal.record_proof(ask_id, proof.clone()) {
+                log::error!("Failed to update journal for ask {}: {}", ask_id, err);
+            }
+            proof
+        }
+    };
+
+    let closed_reason = *closed.borrow();
+    if let Some(reason) = closed_reason {
+        return call_off(ask_context, ask_id, market_id, reason, false).await;
+    }
+    // our own submission emits ProofCreated as well
+    ask_context.watchlist.unwatch(&ask_id);
+    submit(ask_context, ask_id, proof).await;
+}
+
+// Stops work on an ask that was closed on-chain, cancelling its generation if still running
+async fn call_off(
+    ask_context: &AskContext,
+    ask_id: U256,
+    market_id: U256,
+    reason: AskClosed,
+    cancel: bool,
+) {
+    log::warn!(
+        "Ask {} was closed on-chain ({}), dropping it",
+        ask_id,
+        reason.label()
+    );
+    metrics::ASKS_CLOSED
+        .with_label_values(&[reason.label()])
+        .inc();
+    if cancel {
+        cancel_generation(ask_context, ask_id, market_id).await;
+    }
+    if let Err(err) = ask_context
+        .journal
+        .record_dropped(ask_id, format!("closed on-chain: {}", reason.label()))
+    {
+        log::error!("Failed to update journal for ask {}: {}", ask_id, err);
+    }
+}
+
+// Asks the market's generator to stop proving the ask
+async fn cancel_generation(ask_context: &AskContext, ask_id: U256, market_id: U256) {
+    let markets = ask_context.markets();
+    if let Some(generator) = markets.generators.get(&market_id.to_string()) {
+        if let Err(err) = generator.cancel(ask_id).await {
+            log::warn!(
+                "Failed to cancel ask {} on generator {}: {}",
+                ask_id,
+                generator.endpoint(),
+                err
+            );
+        }
+    }
+}
+
+// Fetches and decrypts the inputs of the ask and has the market's generator prove them.
+// Errors whose policy is to retry are retried with a growing delay a few times.
+async fn generate(
+    ask_context: &AskContext,
+    record: &AskRecord,
+    ecies_private_key: &[u8; 32],
+    deadline: Option<Instant>,
+) -> Result<listener::Proof, PipelineError> {
+    // the ask was created before it was assigned
+    let task_block = record
+        .block_number
+        .unwrap_or_else(|| ask_context.current_block());
+    let mut attempt = 1;
+    loop {
+        let markets = ask_context.markets();
+        let generate_proof_args = GenerateProofParams {
+            ask_id: record.ask_id,
+            new_acl: record.new_acl.clone(),
+            proof_market_place_contract_http: Arc::clone(&ask_context.proof_marketplace_http),
+            ecies_private_key,
+            journal: &ask_context.journal,
+            start_block: &ask_context.start_block,
+            task_block: &task_block,
+            markets: &markets.details,
+            generators: &markets.generators,
+            deadline,
+        };
+        let err = match listener::generate_proof(generate_proof_args).await {
+            Ok(proof) => return Ok(proof),
+            Err(err) => err,
+        };
+        metrics::PIPELINE_ERRORS
+            .with_label_values(&[err.kind()])
+            .inc();
+        if err.action() != Action::Retry || attempt >= MAX_GENERATION_ATTEMPTS {
+            return Err(err);
+        }
+        log::warn!(
+            "Attempt {} for ask {} failed, retrying: {}",
+            attempt,
+            record.ask_id,
+            err
+        );
+        tokio::time::sleep(GENERATION_RETRY_DELAY * attempt).await;
+        attempt += 1;
+    }
+}
+
+// Submits the proof and journals the outcome
+async fn submit(ask_context: &AskContext, ask_id: U256, proof: listener::Proof) {
+    let journal = &ask_context.journal;
+    log::info!("{:?}", &proof);
+    if ask_context.dry_run {
+        return log::info!("Dry run, not submitting the proof for ask {}", ask_id);
+    }
+
+    match &proof {
+        listener::Proof::ValidProof(_) => log::info!("Submitting proof on-chain..."),
+        listener::Proof::InvalidProof(_) => log::info!("Submitting signature on-chain..."),
+    }
+    let outcome = ask_context
+        .submitter
+        .submit(ask_id, proof, |tx_hash| {
+            if let Err(err) = journal.record_submission(ask_id, tx_hash) {
+                log::error!("Failed to update journal for ask {}: {}", ask_id, err);
+            }
+        })
+        .await;
+
+    let journal_update = match outcome {
+        SubmissionOutcome::Confirmed(tx_hash) => {
+            log::info!(
+                "Submitted proof for ask with id : {} via transaction {:?}",
+                ask_id,
+                tx_hash
+            );
+            journal.update_stage(ask_id, AskStage::Confirmed)
+        }
+        SubmissionOutcome::Reverted(tx_hash) => {
+            log::error!(
+                "Proof submission for ASK ID : {} reverted in transaction {:?}",
+                ask_id,
+                tx_hash
+            );
+            journal.record_failure(ask_id, format!("transaction {:?} reverted", tx_hash))
+        }
+        SubmissionOutcome::Failed(reason) => {
+            let err = PipelineError::SubmissionFailed(reason);
+            metrics::PIPELINE_ERRORS
+                .with_label_values(&[err.kind()])
+                .inc();
+            log::error!("Error in submitting proof for ASK ID : {}: {}", ask_id, err);
+            journal.record_failure(ask_id, err.to_string())
+        }
+    };
+    if let Err(err) = journal_update {
+        log::error!("Failed to update journal for ask {}: {}", ask_id, err);
+    }
+}
+
+/// Re-drives a single ask end-to-end, whether or not the listener has processed it before.
+/// Asks that are no longer assigned are proven but not submitted.
+pub async fn replay_ask(ask_context: &AskContext, ask_id: U256) -> Result<(), Box<dyn Error>> {
+    let proof_marketplace_http = &ask_context.proof_marketplace_http;
+    let latest_block = ask_context.current_block();
+
+    // the latest assignment wins if the ask was reassigned
+    let mut task_created = None;
+    let mut from_block = ask_context.start_block;
+    while from_block <= latest_block {
+        let to_block = latest_block.min(from_block + 9999);
+        let events = proof_marketplace_http
+            .task_created_filter()
+            .from_block(from_block)
+            .to_block(to_block)
+            .topic1(ask_id)
+            .query()
+            .await?;
+        if let Some(event) = events.into_iter().last() {
+            task_created = Some(event);
+        }
+        from_block = to_block + 1;
+    }
+    let event = task_created.ok_or("TaskCreated event of the ask not found")?;
+    let generator = ask_context
+        .generator(&event.generator)
+        .ok_or("The ask is assigned to a generator that is not configured")?;
+
+    log::info!(
+        "Replaying ask {} assigned to generator {:?}",
+        ask_id,
+        event.generator
+    );
+    let record = AskRecord::new(ask_id, event.generator, event.new_acl, None);
+    let proof = generate(
+        ask_context,
+        &record,
+        &generator.ecies_priv_key.serialize(),
+        None,
+    )
+    .await?;
+
+    let ask_state = ask::get_ask_state(proof_marketplace_http.get_ask_state(ask_id).await?);
+    if ask_state != ask::AskState::Assigned {
+        log::info!("{:?}", &proof);
+        log::info!(
+            "Ask {} is {:?}, not submitting the proof",
+            ask_id,
+            ask_state
+        );
+        return Ok(());
+    }
+    submit(ask_context, ask_id, proof).await;
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use crate::journal::Journal;
+use ethers::prelude::*;
+use std::error::Error;
+
+/// Compares the journaled block hashes with the canonical chain.
+///
+/// Returns `None` when the newest tracked block is still canonical, otherwise the newest tracked
+/// block that survived the reorg (the fork point) from which processing has to restart.
+pub async fn find_fork_point<M: Middleware>(
+    provider: &M,
+    journal: &Journal,
+) -> Result<Option<U64>, Box<dyn Error>>
+where
+    M::Error: 'static,
+{
+    let tracked = journal.tracked_block_hashes()?;
+    let mut oldest_tracked = None;
+
+    for (index, (block_number, hash)) in tracked.iter().enumerate() {
+        let canonical_hash = provider
+            .get_block(*block_number)
+            .await?
+            .and_then(|block| block.hash);
+        if canonical_hash == Some(*hash) {
+            return Ok(if index == 0 {
+                None
+            } else {
+                Some(*block_number)
+            });
+        }
+        log::warn!(
+            "Block {} hash changed from {:?} to {:?}",
+            block_number,
+            hash,
+            canonical_hash
+        );
+        oldest_tracked = Some(*block_number);
+    }
+
+    // reorg deeper than what we track, restart just before the oldest tracked block
+    Ok(oldest_tracked.map(|block| block - 1))
+}
@@ -0,0 +1,355 @@
+use crate::journal::AskRecord;
+use ethers::prelude::*;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// weight of the newest sample in the generator latency moving average
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// An assigned ask waiting for a proof generation slot.
+#[derive(Debug, Clone)]
+pub struct Job {
+    // index of the chain the ask was assigned on
+    pub chain: usize,
+    pub record: AskRecord,
+    pub ecies_private_key: [u8; 32],
+    pub market_id: U256,
+    pub reward: U256,
+    // block by which the proof has to be submitted, zero if the ask has none
+    pub deadline: U256,
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Job {}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Job {
+    // the max-heap pops the earliest deadline first, higher rewards break ties
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        let deadline = |job: &Job| {
+            if job.deadline.is_zero() {
+                U256::MAX
+            } else {
+                job.deadline
+            }
+        };
+        deadline(other)
+            .cmp(&deadline(self))
+            .then(self.reward.cmp(&other.reward))
+            .then(other.record.ask_id.cmp(&self.record.ask_id))
+    }
+}
+
+pub enum Dispatch {
+    // run the job, the proof has to be ready within the given time (if the ask has a deadline)
+    Run(Job, Option<Duration>),
+    // the job can no longer finish before its deadline
+    Abandon(Job),
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    queue: BinaryHeap<Job>,
+    running: HashMap<U256, usize>,
+    running_total: usize,
+    latency: HashMap<U256, Duration>,
+    market_concurrency: HashMap<U256, usize>,
+}
+
+// Deadlines are in blocks of the ask's chain
+struct ChainClock {
+    current_block: AtomicU64,
+    block_time: Duration,
+}
+
+/// Orders pending asks of every chain by deadline and reward and hands them out while respecting
+/// the global and per market concurrency limits.
+pub struct Scheduler {
+    state: Mutex<SchedulerState>,
+    notify: Notify,
+    chains: Vec<ChainClock>,
+    max_concurrency: usize,
+}
+
+impl Scheduler {
+    /// `block_times` holds the block time of every chain, by chain index.
+    pub fn new(
+        block_times: Vec<Duration>,
+        max_concurrency: usize,
+        market_concurrency: HashMap<U256, usize>,
+    ) -> Self {
+        Self {
+            state: Mutex::new(SchedulerState {
+                market_concurrency,
+                ..Default::default()
+            }),
+            notify: Notify::new(),
+            chains: block_times
+                .into_iter()
+                .map(|block_time| ChainClock {
+                    current_block: AtomicU64::new(0),
+                    block_time,
+                })
+                .collect(),
+            max_concurrency,
+        }
+    }
+
+    /// Replaces the per market limits, jobs already running are not affected.
+    pub fn set_market_concurrency(&self, market_concurrency: HashMap<U256, usize>) {
+        self.state.lock().unwrap().market_concurrency = market_concurrency;
+        self.notify.notify_one();
+    }
+
+    pub fn set_current_block(&self, chain: usize, block: U64) {
+        self.chains[chain]
+            .current_block
+            .fetch_max(block.as_u64(), Ordering::SeqCst);
+    }
+
+    pub fn current_block(&self, chain: usize) -> U64 {
+        self.chains[chain]
+            .current_block
+            .load(Ordering::SeqCst)
+            .into()
+    }
+
+    /// Queues the job, or hands it back if it can no longer finish before its deadline.
+    pub fn enqueue(&self, job: Job) -> Result<(), Box<Job>> {
+        let mut state = self.state.lock().unwrap();
+        if !self.is_feasible(&state, &job) {
+            return Err(Box::new(job));
+        }
+        state.queue.push(job);
+        drop(state);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Takes the most urgent job that has a free slot, or one that has to be abandoned.
+    pub fn next(&self) -> Option<Dispatch> {
+        let mut state = self.state.lock().unwrap();
+        if state.running_total >= self.max_concurrency {
+            return None;
+        }
+
+        let mut skipped = vec![];
+        let mut dispatch = None;
+        while let Some(job) = state.queue.pop() {
+            if !self.is_feasible(&state, &job) {
+                dispatch = Some(Dispatch::Abandon(job));
+                break;
+            }
+            let running = state.running.get(&job.market_id).copied().unwrap_or(0);
+            if running >= self.market_limit(&state, &job.market_id) {
+                skipped.push(job);
+                continue;
+            }
+            *state.running.entry(job.market_id).or_insert(0) += 1;
+            state.running_total += 1;
+            let time_left = self.time_left(job.chain, job.deadline);
+            dispatch = Some(Dispatch::Run(job, time_left));
+            break;
+        }
+        state.queue.extend(skipped);
+        dispatch
+    }
+
+    /// Waits until a job is queued or a slot frees up.
+    pub async fn wait(&self) {
+        self.notify.notified().await
+    }
+
+    pub fn finish(&self, market_id: &U256) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(running) = state.running.get_mut(market_id) {
+            *running = running.saturating_sub(1);
+        }
+        state.running_total = state.running_total.saturating_sub(1);
+        drop(state);
+        self.notify.notify_one();
+    }
+
+    pub fn record_latency(&self, market_id: U256, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let average = match state.latency.get(&market_id) {
+            Some(average) => {
+                average.mul_f64(1.0 - LATENCY_SMOOTHING) + latency.mul_f64(LATENCY_SMOOTHING)
+            }
+            None => latency,
+        };
+        log::debug!(
+            "Generator latency for market {} is now {:?}",
+            market_id,
+            average
+        );
+        state.latency.insert(market_id, average);
+    }
+
+    /// Time until the deadline block is mined on `chain`, `None` if the ask has no deadline.
+    pub fn time_left(&self, chain: usize, deadline: U256) -> Option<Duration> {
+        if deadline.is_zero() {
+            return None;
+        }
+        let current_block = U256::from(self.current_block(chain).as_u64());
+        let blocks_left = deadline.saturating_sub(current_block).min(u32::MAX.into());
+        Some(self.chains[chain].block_time * blocks_left.as_u32())
+    }
+
+    fn market_limit(&self, state: &SchedulerState, market_id: &U256) -> usize {
+        state
+            .market_concurrency
+            .get(market_id)
+            .copied()
+            .unwrap_or(self.max_concurrency)
+    }
+
+    fn is_feasible(&self, state: &SchedulerState, job: &Job) -> bool {
+        match self.time_left(job.chain, job.deadline) {
+            None => true,
+            Some(time_left) if time_left.is_zero() => false,
+            Some(time_left) => match state.latency.get(&job.market_id) {
+                Some(latency) => *latency < time_left,
+                None => true,
+            },
+        }
+    }
+}
+
+/// Average block time over the last `sample` blocks.
+pub async fn estimate_block_time<M: Middleware>(
+    provider: &M,
+    sample: u64,
+) -> Result<Duration, Box<dyn std::error::Error>>
+where
+    M::Error: 'static,
+{
+    let latest = provider.get_block_number().await?;
+    let earlier = latest.saturating_sub(sample.into());
+    let latest_block = provider
+        .get_block(latest)
+        .await?
+        .ok_or("latest block missing")?;
+    let earlier_block = provider
+        .get_block(earlier)
+        .await?
+        .ok_or("sample block missing")?;
+    let elapsed_ms = latest_block
+        .timestamp
+        .saturating_sub(earlier_block.timestamp)
+        .as_u64()
+        * 1000;
+    let blocks = (latest - earlier).as_u64().max(1);
+    Ok(Duration::from_millis((elapsed_ms / blocks).max(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Bytes;
+
+    fn job(ask_id: u64, market_id: u64, reward: u64, deadline: u64) -> Job {
+        Job {
+            chain: 0,
+            record: AskRecord::new(ask_id.into(), Address::zero(), Bytes::new(), None),
+            ecies_private_key: [0; 32],
+            market_id: market_id.into(),
+            reward: reward.into(),
+            deadline: deadline.into(),
+        }
+    }
+
+    fn next_ask(scheduler: &Scheduler) -> Option<u64> {
+        match scheduler.next() {
+            Some(Dispatch::Run(job, _)) => Some(job.record.ask_id.as_u64()),
+            Some(Dispatch::Abandon(job)) => panic!("ask {} abandoned", job.record.ask_id),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn dispatches_by_deadline_then_reward() {
+        let scheduler = Scheduler::new(vec![Duration::from_secs(1)], 10, HashMap::new());
+        scheduler.set_current_block(0, 100.into());
+        scheduler.enqueue(job(1, 1, 10, 500)).unwrap();
+        scheduler.enqueue(job(2, 1, 10, 200)).unwrap();
+        scheduler.enqueue(job(3, 1, 50, 500)).unwrap();
+        scheduler.enqueue(job(4, 1, 99, 0)).unwrap();
+
+        assert_eq!(next_ask(&scheduler), Some(2));
+        assert_eq!(next_ask(&scheduler), Some(3));
+        assert_eq!(next_ask(&scheduler), Some(1));
+        assert_eq!(next_ask(&scheduler), Some(4));
+        assert_eq!(next_ask(&scheduler), None);
+    }
+
+    #[test]
+    fn respects_market_concurrency() {
+        let scheduler = Scheduler::new(
+            vec![Duration::from_secs(1)],
+            10,
+            HashMap::from([(U256::from(1), 1)]),
+        );
+        scheduler.enqueue(job(1, 1, 10, 0)).unwrap();
+        scheduler.enqueue(job(2, 1, 10, 0)).unwrap();
+        scheduler.enqueue(job(3, 2, 10, 0)).unwrap();
+
+        assert_eq!(next_ask(&scheduler), Some(1));
+        // market 1 is busy, ask 2 has to wait
+        assert_eq!(next_ask(&scheduler), Some(3));
+        assert_eq!(next_ask(&scheduler), None);
+        scheduler.finish(&1.into());
+        assert_eq!(next_ask(&scheduler), Some(2));
+    }
+
+    #[test]
+    fn refuses_asks_that_cannot_meet_the_deadline() {
+        let scheduler = Scheduler::new(vec![Duration::from_secs(1)], 10, HashMap::new());
+        scheduler.set_current_block(0, 100.into());
+        scheduler.record_latency(1.into(), Duration::from_secs(30));
+
+        assert!(scheduler.enqueue(job(1, 1, 10, 120)).is_err());
+        assert!(scheduler.enqueue(job(2, 1, 10, 90)).is_err());
+        assert!(scheduler.enqueue(job(3, 1, 10, 200)).is_ok());
+
+        // the chain moved on while the job was queued
+        scheduler.set_current_block(0, 180.into());
+        assert!(matches!(scheduler.next(), Some(Dispatch::Abandon(_))));
+    }
+
+    #[test]
+    fn counts_deadlines_in_blocks_of_the_asks_chain() {
+        let scheduler = Scheduler::new(
+            vec![Duration::from_secs(12), Duration::from_millis(250)],
+            10,
+            HashMap::new(),
+        );
+        scheduler.set_current_block(0, 100.into());
+        scheduler.set_current_block(1, 5000.into());
+        scheduler.record_latency(1.into(), Duration::from_secs(60));
+
+        // 20 blocks are 240s on the first chain but only 5s on the second
+        assert!(scheduler.enqueue(job(1, 1, 10, 120)).is_ok());
+        let mut fast = job(2, 1, 10, 5020);
+        fast.chain = 1;
+        assert!(scheduler.enqueue(fast).is_err());
+        assert_eq!(
+            scheduler.time_left(1, 5020.into()),
+            Some(Duration::from_secs(5))
+        );
+    }
+}
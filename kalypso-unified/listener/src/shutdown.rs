@@ -0,0 +1,86 @@
+use crate::processor::{AskContext, Shared};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// SIGINT (ctrl-c) and SIGTERM, the latter is what container orchestrators send on shutdown.
+pub struct ShutdownSignals {
+    interrupt: Signal,
+    terminate: Signal,
+}
+
+impl ShutdownSignals {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    /// Resolves with the name of the next signal received.
+    pub async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.interrupt.recv() => "SIGINT",
+            _ = self.terminate.recv() => "SIGTERM",
+        }
+    }
+}
+
+/// Stops dispatching queued asks and waits up to `timeout` for the running ones to be proven
+/// and submitted. Another signal cuts the wait short. Asks that don't finish stay in the
+/// journal and are resumed on the next start.
+pub async fn drain(
+    shared: &Shared,
+    chains: &[Arc<AskContext>],
+    signals: &mut ShutdownSignals,
+    timeout: Duration,
+) {
+    shared.draining.store(true, Ordering::Release);
+    let started = Instant::now();
+    log::info!(
+        "Draining, waiting up to {:?} for {} running asks",
+        timeout,
+        shared.running_jobs.load(Ordering::Acquire)
+    );
+
+    let wait = async {
+        while shared.running_jobs.load(Ordering::Acquire) > 0 && started.elapsed() < timeout {
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    };
+    tokio::select! {
+        _ = wait => {}
+        signal = signals.recv() => log::warn!("Received {} while draining, stopping now", signal),
+    }
+
+    let running = shared.running_jobs.load(Ordering::Acquire);
+    if running == 0 {
+        log::info!("Drained in {:?}", started.elapsed());
+    } else {
+        log::warn!(
+            "Stopping with {} asks still running, they are resumed on the next start",
+            running
+        );
+    }
+    for ask_context in chains {
+        match ask_context.journal.unfinished_asks() {
+            Ok(unfinished) if !unfinished.is_empty() => log::info!(
+                "Unfinished asks left in the journal of {}: {:?}",
+                ask_context.chain_name,
+                unfinished
+                    .iter()
+                    .map(|record| (record.ask_id, record.stage))
+                    .collect::<Vec<_>>()
+            ),
+            Ok(_) => {}
+            Err(err) => log::error!(
+                "Failed to read the journal of {}: {}",
+                ask_context.chain_name,
+                err
+            ),
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use crate::key_provider::ListenerSigner;
+use crate::listener::Proof;
+use crate::metrics;
+use bindings::proof_marketplace::ProofMarketplace;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+type SignerClient = SignerMiddleware<Provider<Http>, ListenerSigner>;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SubmitterConfig {
+    // attempts (sends and fee bumps) before an ask is given up
+    pub max_retries: u32,
+    // time to wait for a receipt before replacing the transaction with higher fees
+    pub stuck_timeout_secs: u64,
+    pub fee_bump_percent: u64,
+    // upper bound for max_fee_per_gas in wei, fees are never bumped above it
+    pub max_fee_per_gas: Option<U256>,
+}
+
+impl Default for SubmitterConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            stuck_timeout_secs: 60,
+            fee_bump_percent: 20,
+            max_fee_per_gas: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmissionOutcome {
+    Confirmed(H256),
+    Reverted(H256),
+    Failed(String),
+}
+
+/// What to do after the node refused a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SendError {
+    // an earlier transaction of this submission holds the nonce, wait for it
+    AlreadySent,
+    // the local nonce was used by someone else (another process, before a restart), take a
+    // fresh one from the chain
+    StaleNonce,
+    Retry,
+}
+
+fn classify_send_error(message: &str, sent_before: bool) -> SendError {
+    let message = message.to_lowercase();
+    let nonce_used = message.contains("nonce too low") || message.contains("already known");
+    match (nonce_used, sent_before) {
+        (true, true) => SendError::AlreadySent,
+        (true, false) => SendError::StaleNonce,
+        (false, _) => SendError::Retry,
+    }
+}
+
+/// Hands out consecutive nonces for the gas payer so submissions can be pipelined.
+struct NonceManager {
+    next: tokio::sync::Mutex<Option<U256>>,
+}
+
+impl NonceManager {
+    async fn next(&self, client: &SignerClient) -> Result<U256, ProviderError> {
+        let mut next = self.next.lock().await;
+        let nonce = match *next {
+            Some(nonce) => nonce,
+            None => {
+                client
+                    .provider()
+                    .get_transaction_count(client.address(), Some(BlockNumber::Pending.into()))
+                    .await?
+            }
+        };
+        *next = Some(nonce + 1);
+        Ok(nonce)
+    }
+
+    // forget the local nonce, the next one is fetched from the chain again
+    async fn resync(&self) {
+        *self.next.lock().await = None;
+    }
+}
+
+/// Submits proofs with locally managed nonces, EIP-1559 fees and replacement-by-fee for
+/// transactions that are not mined in time.
+pub struct Submitter {
+    client: Arc<SignerClient>,
+    proof_marketplace: ProofMarketplace<SignerClient>,
+    nonce_manager: NonceManager,
+    config: SubmitterConfig,
+}
+
+impl Submitter {
+    pub fn new(
+        client: Arc<SignerClient>,
+        proof_marketplace_address: Address,
+        config: SubmitterConfig,
+    ) -> Self {
+        Self {
+            proof_marketplace: ProofMarketplace::new(proof_marketplace_address, client.clone()),
+            client,
+            nonce_manager: NonceManager {
+                next: tokio::sync::Mutex::new(None),
+            },
+            config,
+        }
+    }
+
+    /// Submits the proof for `ask_id` and waits for the outcome. `on_broadcast` is called with
+    /// the hash of every transaction (including replacements) sent to the network.
+    pub async fn submit<F: Fn(H256)>(
+        &self,
+        ask_id: U256,
+        proof: Proof,
+        on_broadcast: F,
+    ) -> SubmissionOutcome {
+        let call = match proof {
+            Proof::ValidProof(proof) => self.proof_marketplace.submit_proof(ask_id, proof),
+            Proof::InvalidProof(signature) => self
+                .proof_marketplace
+                .submit_proof_for_invalid_inputs(ask_id, signature),
+        };
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .from(self.client.address())
+            .to(self.proof_marketplace.address())
+            .data(call.calldata().unwrap_or_default())
+            .chain_id(self.client.signer().chain_id())
+            .into();
+
+        let mut attempts = 0;
+        let gas = loop {
+            match self.client.estimate_gas(&tx, None).await {
+                Ok(gas) => break gas * 120 / 100,
+                Err(err) => {
+                    attempts += 1;
+                    if attempts > self.config.max_retries {
+                        return SubmissionOutcome::Failed(format!(
+                            "gas estimation failed: {}",
+                            err
+                        ));
+                    }
+                    log::warn!("Gas estimation for ask {} failed: {}", ask_id, err);
+                    tokio::time::sleep(backoff(attempts)).await;
+                }
+            }
+        };
+        let (mut max_fee, mut priority_fee) = loop {
+            match self.client.estimate_eip1559_fees(None).await {
+                Ok(fees) => break fees,
+                Err(err) => {
+                    attempts += 1;
+                    if attempts > self.config.max_retries {
+                        return SubmissionOutcome::Failed(format!(
+                            "fee estimation failed: {}",
+                            err
+                        ));
+                    }
+                    log::warn!("Fee estimation for ask {} failed: {}", ask_id, err);
+                    tokio::time::sleep(backoff(attempts)).await;
+                }
+            }
+        };
+        if let Some(cap) = self.config.max_fee_per_gas {
+            max_fee = max_fee.min(cap);
+            priority_fee = priority_fee.min(max_fee);
+        }
+        let mut nonce = match self.nonce_manager.next(&self.client).await {
+            Ok(nonce) => nonce,
+            Err(err) => return SubmissionOutcome::Failed(format!("nonce fetch failed: {}", err)),
+        };
+
+        tx.set_gas(gas);
+        tx.set_nonce(nonce);
+        let mut sent: Vec<H256> = vec![];
+        // false once the fee cap leaves no room for a replacement, the sent transactions are
+        // only waited on from then on
+        let mut resend = true;
+
+        loop {
+            if let TypedTransaction::Eip1559(ref mut inner) = tx {
+                inner.max_fee_per_gas = Some(max_fee);
+                inner.max_priority_fee_per_gas = Some(priority_fee);
+            }
+
+            let send_result = if resend {
+                Some(self.client.send_transaction(tx.clone(), None).await)
+            } else {
+                None
+            };
+            match send_result {
+                None => {}
+                Some(Ok(pending_tx)) => {
+                    let tx_hash = pending_tx.tx_hash();
+                    log::info!(
+                        "Sent transaction {:?} for ask {} with nonce {} and max fee {}",
+                        tx_hash,
+                        ask_id,
+                        nonce,
+                        max_fee
+                    );
+                    sent.push(tx_hash);
+                    on_broadcast(tx_hash);
+                }
+                Some(Err(err)) => match classify_send_error(&err.to_string(), !sent.is_empty()) {
+                    SendError::AlreadySent => log::debug!(
+                        "Nonce {} already used by an earlier transaction of ask {}",
+                        nonce,
+                        ask_id
+                    ),
+                    kind => {
+                        attempts += 1;
+                        log::warn!("Sending transaction for ask {} failed: {}", ask_id, err);
+                        if attempts > self.config.max_retries {
+                            if sent.is_empty() {
+                                // nothing was broadcast with this nonce, don't leave a gap behind
+                                self.nonce_manager.resync().await;
+                            }
+                            return SubmissionOutcome::Failed(format!(
+                                "sending transaction failed: {}",
+                                err
+                            ));
+                        }
+                        if kind == SendError::StaleNonce {
+                            self.nonce_manager.resync().await;
+                            nonce = match self.nonce_manager.next(&self.client).await {
+                                Ok(nonce) => nonce,
+                                Err(err) => {
+                                    return SubmissionOutcome::Failed(format!(
+                                        "nonce fetch failed: {}",
+                                        err
+                                    ))
+                                }
+                            };
+                            log::info!(
+                                "Resending the proof of ask {} with nonce {}",
+                                ask_id,
+                                nonce
+                            );
+                            tx.set_nonce(nonce);
+                        } else {
+                            tokio::time::sleep(backoff(attempts)).await;
+                        }
+                        continue;
+                    }
+                },
+            }
+
+            if let Some(outcome) = self.wait_for_receipt(&sent).await {
+                return outcome;
+            }
+
+            attempts += 1;
+            if attempts > self.config.max_retries {
+                return SubmissionOutcome::Failed(format!(
+                    "transaction not mined after {} attempts, last sent {:?}",
+                    attempts,
+                    sent.last()
+                ));
+            }
+            match replacement_fees(
+                max_fee,
+                priority_fee,
+                self.config.fee_bump_percent,
+                self.config.max_fee_per_gas,
+            ) {
+                Some((bumped_max_fee, bumped_priority_fee)) => {
+                    max_fee = bumped_max_fee;
+                    priority_fee = bumped_priority_fee;
+                    resend = true;
+                    log::warn!(
+                        "Transaction for ask {} is stuck, replacing it with max fee {}",
+                        ask_id,
+                        max_fee
+                    );
+                }
+                None => {
+                    resend = false;
+                    log::warn!(
+                        "Transaction for ask {} is stuck and max_fee_per_gas {:?} blocks a replacement, waiting for the sent transactions",
+                        ask_id,
+                        self.config.max_fee_per_gas
+                    );
+                }
+            }
+        }
+    }
+
+    // Polls receipts of every transaction sent for the nonce until one is mined or the stuck timeout passes
+    async fn wait_for_receipt(&self, sent: &[H256]) -> Option<SubmissionOutcome> {
+        let deadline = Instant::now() + Duration::from_secs(self.config.stuck_timeout_secs);
+        while Instant::now() < deadline {
+            for tx_hash in sent {
+                match self.client.get_transaction_receipt(*tx_hash).await {
+                    Ok(Some(receipt)) => {
+                        if let Some(gas_used) = receipt.gas_used {
+                            metrics::SUBMISSION_GAS_USED.observe(gas_used.as_u128() as f64);
+                        }
+                        return Some(if receipt.status == Some(1.into()) {
+                            SubmissionOutcome::Confirmed(*tx_hash)
+                        } else {
+                            SubmissionOutcome::Reverted(*tx_hash)
+                        });
+                    }
+                    Ok(None) => {}
+                    Err(err) => log::warn!("Failed fetching receipt of {:?}: {}", tx_hash, err),
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+        None
+    }
+}
+
+fn bump_fee(fee: U256, bump_percent: u64) -> U256 {
+    // nodes reject replacements that don't raise the fees by at least 10%
+    let bump_percent = bump_percent.max(10);
+    fee * (100 + bump_percent) / 100 + 1
+}
+
+// Fees of a replacement transaction, `None` if the cap keeps them below the 10% raise nodes
+// require to accept a replacement.
+fn replacement_fees(
+    max_fee: U256,
+    priority_fee: U256,
+    bump_percent: u64,
+    cap: Option<U256>,
+) -> Option<(U256, U256)> {
+    let mut bumped_max_fee = bump_fee(max_fee, bump_percent);
+    let mut bumped_priority_fee = bump_fee(priority_fee, bump_percent);
+    if let Some(cap) = cap {
+        bumped_max_fee = bumped_max_fee.min(cap);
+        bumped_priority_fee = bumped_priority_fee.min(bumped_max_fee);
+    }
+    let accepted = |old: U256, new: U256| new > old && new >= old * 110 / 100;
+    if accepted(max_fee, bumped_max_fee) && accepted(priority_fee, bumped_priority_fee) {
+        Some((bumped_max_fee, bumped_priority_fee))
+    } else {
+        None
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(500 * 2u64.pow(attempt.min(6)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bumped_fee_is_accepted_as_replacement() {
+        assert_eq!(bump_fee(1000.into(), 20), U256::from(1201));
+        assert_eq!(bump_fee(1000.into(), 5), U256::from(1101));
+        assert_eq!(bump_fee(0.into(), 20), U256::from(1));
+    }
+
+    #[test]
+    fn capped_fees_stop_replacements() {
+        assert_eq!(
+            replacement_fees(1000.into(), 100.into(), 20, None),
+            Some((1201.into(), 121.into()))
+        );
+        assert_eq!(
+            replacement_fees(1000.into(), 100.into(), 20, Some(1150.into())),
+            Some((1150.into(), 121.into()))
+        );
+        assert_eq!(
+            replacement_fees(1000.into(), 100.into(), 20, Some(1050.into())),
+            None
+        );
+        assert_eq!(
+            replacement_fees(1000.into(), 1000.into(), 20, Some(1000.into())),
+            None
+        );
+    }
+
+    #[test]
+    fn stale_nonce_is_retried_with_a_fresh_one() {
+        assert_eq!(
+            classify_send_error("(code: -32000, message: nonce too low, data: None)", false),
+            SendError::StaleNonce
+        );
+        assert_eq!(
+            classify_send_error("already known", false),
+            SendError::StaleNonce
+        );
+        assert_eq!(
+            classify_send_error("Nonce too low", true),
+            SendError::AlreadySent
+        );
+        assert_eq!(
+            classify_send_error("replacement transaction underpriced", false),
+            SendError::Retry
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use ethers::prelude::*;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+pub enum SubscriptionEvent {
+    Log(Box<Log>),
+    NewHead(U64),
+}
+
+/// Subscribes to `filter` logs and new block headers over websocket.
+///
+/// Events are forwarded on the returned channel, which is closed as soon as the connection
+/// fails or no new header arrives within `head_timeout`, so the caller can fall back to polling.
+pub fn spawn_subscription(
+    ws_url: String,
+    filter: Filter,
+    head_timeout: Duration,
+) -> mpsc::Receiver<SubscriptionEvent> {
+    let (tx, rx) = mpsc::channel(100);
+    tokio::spawn(async move {
+        if let Err(err) = run_subscription(&ws_url, &filter, head_timeout, tx).await {
+            log::error!("Websocket subscription failed: {}", err);
+        }
+    });
+    rx
+}
+
+async fn run_subscription(
+    ws_url: &str,
+    filter: &Filter,
+    head_timeout: Duration,
+    tx: mpsc::Sender<SubscriptionEvent>,
+) -> Result<(), ProviderError> {
+    let provider = Provider::<Ws>::connect(ws_url).await?;
+    let mut log_stream = provider.subscribe_logs(filter).await?;
+    let mut head_stream = provider.subscribe_blocks().await?;
+    log::info!("Subscribed to marketplace logs over websocket");
+
+    loop {
+        let event = tokio::select! {
+            log = log_stream.next() => log.map(|log| SubscriptionEvent::Log(Box::new(log))),
+            block = head_stream.next() => block.and_then(|block| block.number).map(SubscriptionEvent::NewHead),
+            _ = tokio::time::sleep(head_timeout) => {
+                log::warn!("No new block received over websocket for {:?}", head_timeout);
+                None
+            }
+        };
+
+        match event {
+            Some(event) => {
+                if tx.send(event).await.is_err() {
+                    // receiver dropped, nobody is listening anymore
+                    return Ok(());
+                }
+            }
+            None => {
+                log::warn!("Websocket subscription stream ended");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Subscribed and back-filled logs waiting for their block to be confirmed, in chain order.
+/// A log delivered both ways is kept once, a retracted log is dropped again.
+#[derive(Default)]
+pub struct PendingLogs {
+    logs: BTreeMap<(U64, U256), Log>,
+}
+
+impl PendingLogs {
+    pub fn insert(&mut self, log: Log) {
+        let (Some(block_number), Some(log_index)) = (log.block_number, log.log_index) else {
+            return;
+        };
+        if log.removed == Some(true) {
+            self.logs.remove(&(block_number, log_index));
+        } else {
+            self.logs.insert((block_number, log_index), log);
+        }
+    }
+
+    /// Removes and returns the logs up to `block`, oldest first.
+    pub fn take_until(&mut self, block: U64) -> Vec<Log> {
+        let later = self.logs.split_off(&(block + 1, U256::zero()));
+        std::mem::replace(&mut self.logs, later)
+            .into_values()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(block: u64, index: u64, removed: bool) -> Log {
+        Log {
+            block_number: Some(block.into()),
+            log_index: Some(index.into()),
+            removed: Some(removed),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn holds_logs_until_confirmed() {
+        let mut pending = PendingLogs::default();
+        pending.insert(log(12, 0, false));
+        pending.insert(log(10, 3, false));
+        // back-filled again after it was subscribed
+        pending.insert(log(10, 3, false));
+        pending.insert(log(11, 1, false));
+        pending.insert(log(11, 1, true));
+
+        assert!(pending.take_until(9.into()).is_empty());
+        let confirmed: Vec<_> = pending
+            .take_until(11.into())
+            .iter()
+            .map(|log| {
+                (
+                    log.block_number.unwrap().as_u64(),
+                    log.log_index.unwrap().as_u64(),
+                )
+            })
+            .collect();
+        assert_eq!(confirmed, vec![(10, 3)]);
+        assert_eq!(pending.take_until(12.into()).len(), 1);
+    }
+}
@@ -7,10 +7,13 @@ use serde_json::Value;
 struct JsonResponse {
     message: String,
     data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Value>,
 }
 
 struct ResponseOptions {
     data: Option<Value>,
+    meta: Option<Value>,
     message: String,
     status_code: StatusCode,
 }
@@ -28,6 +31,7 @@ impl ResponseHandler {
         JsonResponse {
             message: self.options.message,
             data: self.options.data,
+            meta: self.options.meta,
         }
     }
 
@@ -43,9 +47,20 @@ pub fn response(
     message: &str,
     status_code: StatusCode,
     data: Option<serde_json::Value>,
+) -> HttpResponse {
+    response_with_meta(message, status_code, data, None)
+}
+
+//Generate response with details next to the data, e.g. the content hash of a stored proof
+pub fn response_with_meta(
+    message: &str,
+    status_code: StatusCode,
+    data: Option<serde_json::Value>,
+    meta: Option<serde_json::Value>,
 ) -> HttpResponse {
     let options = ResponseOptions {
         data,
+        meta,
         message: message.to_string(),
         status_code,
     };
@@ -0,0 +1,32 @@
+use ethers::abi::{encode, Token};
+use ethers::types::U256;
+use rand::RngCore;
+use rsa::pkcs8::EncodePublicKey;
+use rsa::{PaddingScheme, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+const KEY_BITS: usize = 2048;
+const MESSAGE_LEN: usize = 256;
+
+/// Prover data of an ask the program accepts: a fresh RSA public key (DER), a random message
+/// and its PKCS#1 v1.5 SHA-256 signature, encoded the way `read_inputs` decodes them.
+pub fn signed_message_input() -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut rng = rand::thread_rng();
+    let private_key = RsaPrivateKey::new(&mut rng, KEY_BITS)?;
+    let public_key_der = RsaPublicKey::from(&private_key).to_public_key_der()?;
+
+    let mut message = vec![0u8; MESSAGE_LEN];
+    rng.fill_bytes(&mut message);
+    let signature = private_key.sign(
+        PaddingScheme::new_pkcs1v15_sign(Some(rsa::hash::Hash::SHA2_256)),
+        &Sha256::digest(&message),
+    )?;
+
+    let inputs = encode(&[
+        Token::Bytes(public_key_der.as_ref().to_vec()),
+        Token::Bytes(message),
+        Token::Bytes(signature),
+    ]);
+    Ok(encode(&[Token::Uint(U256::from(3)), Token::Bytes(inputs)]))
+}
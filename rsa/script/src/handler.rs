@@ -1,173 +1,247 @@
-use actix_web::{get, post};
-use actix_web::{http::StatusCode, web, Responder};
+use actix_web::{delete, get, post};
+use actix_web::{http::StatusCode, web, HttpResponse, Responder};
 
-use ethers::abi::{decode, AbiType, Token};
-use ethers::types::Bytes;
-use reqwest::blocking::Client;
-use serde_json::Value;
+use ethers::types::U256;
 use serde::{Deserialize, Serialize};
-use sp1_sdk::{utils, ProverClient, SP1Proof, SP1Stdin};
-use std::fs;
-use std::vec;
-use uuid::Uuid;
+use serde_json::json;
+use std::sync::Arc;
 
-/// The ELF we want to execute inside the zkVM.
-const ELF: &[u8] = include_bytes!("../../program/elf/riscv32im-succinct-zkvm-elf");
+use crate::benchmark;
+use crate::jobs::{JobRequest, JobState, Jobs};
+use crate::program::Program;
+use crate::proof_mode::{ProofMode, ProofModeConfig};
 
 #[get("/test")]
 async fn test() -> impl Responder {
     common::response("Generator is running", StatusCode::OK, None)
 }
 
-#[get("/benchmark")]
-async fn benchmark() -> impl Responder {
-    common::response(
-        "Benchmarking API is not implemented",
-        StatusCode::NOT_IMPLEMENTED,
-        None,
-    )
+#[derive(Serialize, Debug, Deserialize)]
+struct BenchmarkQuery {
+    // mode to benchmark, the default mode if unset
+    pub mode: Option<ProofMode>,
 }
 
-async fn process_proof(input_data: Vec<u8>) -> impl Responder {
-    let outer_types = vec![
-        <ethers::types::U256 as AbiType>::param_type(), // uint256
-        <Bytes as AbiType>::param_type(),               // bytes (nested)
-    ];
-
-    let outer_decoded: Vec<Token> =
-        decode(&outer_types, &input_data).expect("Decoding outer layer failed");
-
-    let num_bytes: ethers::types::U256 = outer_decoded[0]
-        .clone()
-        .into_uint()
-        .expect("Failed to decode U256");
-    let num_bytes_usize = num_bytes.as_usize();
-
-    let nested_data: Vec<u8> = outer_decoded[1]
-        .clone()
-        .into_bytes()
-        .expect("Failed to decode nested bytes");
-
-    // Now, decode the nested bytes array
-    let nested_types = vec![<Bytes as AbiType>::param_type(); num_bytes_usize];
-
-    let nested_decoded: Vec<Token> =
-        decode(&nested_types, &nested_data).expect("Decoding nested bytes array failed");
-
-    if num_bytes_usize == 0 || num_bytes_usize != nested_decoded.len() {
-        return common::response(
-            "Invalid number of byte inputs",
-            StatusCode::BAD_REQUEST,
-            None,
-        );
+// Proves a freshly signed message through the job pipeline. data holds the proving time in ms,
+// which the generator client reports, meta the full measurements.
+#[get("/benchmark")]
+async fn benchmark(
+    query: web::Query<BenchmarkQuery>,
+    program: web::Data<Program>,
+    jobs: web::Data<Jobs>,
+    modes: web::Data<ProofModeConfig>,
+) -> impl Responder {
+    let input = web::block(|| benchmark::signed_message_input().map_err(|err| err.to_string()))
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(|input| input);
+    let input = match input {
+        Ok(input) => input,
+        Err(err) => {
+            return common::response(
+                &format!("Failed to prepare the benchmark: {}", err),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                None,
+            )
+        }
+    };
+
+    let mode = query.mode.unwrap_or(modes.default);
+    let jobs = jobs.into_inner();
+    let Some(id) = jobs.submit(JobRequest {
+        input,
+        mode,
+        ask_id: None,
+    }) else {
+        return common::response("Benchmark was cancelled", StatusCode::GONE, None);
+    };
+    match jobs.wait(&id).await {
+        Some(status) if status.state == JobState::Done => common::response_with_meta(
+            "Benchmark completed",
+            StatusCode::OK,
+            Some(json!(status.proving_ms.unwrap_or_default().to_string())),
+            Some(json!({
+                "mode": mode,
+                "cycles": status.cycles,
+                "setup_ms": program.setup_ms,
+                "proving_ms": status.proving_ms,
+                "proof_size": status.proof_size,
+            })),
+        ),
+        _ => job_response(&jobs, &id),
     }
+}
 
-    utils::setup_logger();
-
-    // Create a new stdin with the input for the program.
-    let mut stdin = SP1Stdin::new();
+#[get("/vkey")]
+async fn vkey(program: web::Data<Program>) -> impl Responder {
+    common::response(
+        "Verifying key hash of the program",
+        StatusCode::OK,
+        Some(json!({ "vkey_hash": program.vkey_hash() })),
+    )
+}
 
-    for i in 0..=num_bytes_usize - 1 {
-        let input: Vec<u8> = nested_decoded[i]
-            .clone()
-            .into_bytes()
-            .expect("Failed to decode Vec<u8>");
-        stdin.write(&input);
+// Responds with the proof of a finished job, or why there is none.
+fn job_response(jobs: &Jobs, id: &str) -> HttpResponse {
+    match jobs.response_parts(id) {
+        Some((status_code, message, Some(output))) => {
+            common::response_with_meta(&message, status_code, Some(output.data), Some(output.meta))
+        }
+        Some((status_code, message, None)) => common::response(&message, status_code, None),
+        None => common::response("Job not found", StatusCode::NOT_FOUND, None),
     }
+}
 
-    let client = ProverClient::new();
-    let (pk, vk) = client.setup(ELF);
-    let proof = client.prove(&pk, stdin).expect("proving failed");
-
-    // Verify proof.
-    client.verify(&proof, &vk).expect("verification failed");
-
-    // instead of proof-with-pis, use file name generated by uuid, then save file, again read file as bytes and send it in response using hex::encode
-
-    // Generate a unique filename using uuid
-    let filename = format!("proof-{}.bin", Uuid::new_v4());
-
-    // Save the proof to the file
-    proof.save(&filename).expect("saving proof failed");
-
-    let deserialized_proof = SP1Proof::load(&filename).expect("loading proof failed");
-    // Verify the deserialized proof.
-    client
-        .verify(&deserialized_proof, &vk)
-        .expect("verification failed");
-
-    // Read the file as bytes
-    let proof_bytes = fs::read(&filename).expect("reading proof file failed");
-
-    // Upload the file to Pinata IPFS and get the link
-    let jwt = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.eyJ1c2VySW5mb3JtYXRpb24iOnsiaWQiOiJhZGExNTk5Ny02YTQxLTQ1NWMtOTMzZS0yZGM4MTg3MjY1NjciLCJlbWFpbCI6ImFrc2hheS4xMTFtZWhlckBnbWFpbC5jb20iLCJlbWFpbF92ZXJpZmllZCI6dHJ1ZSwicGluX3BvbGljeSI6eyJyZWdpb25zIjpbeyJpZCI6IkZSQTEiLCJkZXNpcmVkUmVwbGljYXRpb25Db3VudCI6MX0seyJpZCI6Ik5ZQzEiLCJkZXNpcmVkUmVwbGljYXRpb25Db3VudCI6MX1dLCJ2ZXJzaW9uIjoxfSwibWZhX2VuYWJsZWQiOmZhbHNlLCJzdGF0dXMiOiJBQ1RJVkUifSwiYXV0aGVudGljYXRpb25UeXBlIjoic2NvcGVkS2V5Iiwic2NvcGVkS2V5S2V5IjoiOWRjYzQyY2I0ODdmZTBhMTVlNmEiLCJzY29wZWRLZXlTZWNyZXQiOiIwYTE4MWI3NmIzNmIxNDBlMzE1ZWVmOGI1MjIyZjQ1OTk3ODI2NWY2NGYxMmVkMWM2MjdjMTU3MzE2YTUyNzdhIiwiaWF0IjoxNzIwMDA1OTU0fQ.Y3hNEN6ll1FEfpCWSeXoXLOLahYkR7O9I9qgg4mQiks"; // replace with your JWT token
-
-    let form = reqwest::blocking::multipart::Form::new()
-        .file("file", &filename)
-        .expect("failed to create form")
-        .text("pinataMetadata", r#"{"name": "File name"}"#)
-        .text("pinataOptions", r#"{"cidVersion": 0}"#);
-
-    let client = Client::new();
-    let res = client
-        .post("https://api.pinata.cloud/pinning/pinFileToIPFS")
-        .header("Authorization", format!("Bearer {}", jwt))
-        .multipart(form)
-        .send()
-        .expect("uploading file failed");
+// Submits the job and holds the request open until it is finished.
+async fn prove_and_wait(jobs: &Arc<Jobs>, request: JobRequest) -> HttpResponse {
+    let Some(id) = jobs.submit(request) else {
+        return common::response("Proof request was cancelled", StatusCode::GONE, None);
+    };
+    jobs.wait(&id).await;
+    job_response(jobs, &id)
+}
 
-    let response_json: Value = res.json().expect("failed to parse response");
-    let ipfs_hash = response_json["IpfsHash"]
-        .as_str()
-        .expect("failed to get IpfsHash");
+#[derive(Serialize, Debug, Deserialize)]
+struct OnlyInput {
+    pub input: String,
+    // mode to benchmark, the default mode if unset
+    pub mode: Option<ProofMode>,
+}
 
-    let url = format!("https://gateway.pinata.cloud/ipfs/{}", ipfs_hash);
+#[post("/customBenchmark")]
+async fn generate_custom_benchmark(
+    _jsonbody: web::Json<OnlyInput>,
+    jobs: web::Data<Jobs>,
+    modes: web::Data<ProofModeConfig>,
+) -> impl Responder {
+    let input_data = hex::decode(&_jsonbody.input).expect("Failed decoding inputs");
+    let request = JobRequest {
+        input: input_data,
+        mode: _jsonbody.mode.unwrap_or(modes.default),
+        ask_id: None,
+    };
+    prove_and_wait(&jobs.into_inner(), request).await
+}
 
-    // Encode the bytes to a hex string
-    let url_hex = hex::encode(url);
+#[post("/generateProof")]
+async fn generate_proof(
+    _jsonbody: web::Json<common::GenerateProofInputs>,
+    jobs: web::Data<Jobs>,
+    modes: web::Data<ProofModeConfig>,
+) -> impl Responder {
+    let request = JobRequest {
+        input: _jsonbody.ask.prover_data.to_vec(),
+        mode: modes.mode(_jsonbody.ask.market_id),
+        ask_id: Some(_jsonbody.ask_id),
+    };
+    prove_and_wait(&jobs.into_inner(), request).await
+}
 
-    fs::remove_file(&filename).expect("removing proof file failed");
+#[derive(Serialize, Debug, Deserialize)]
+struct CancelInput {
+    // decimal, ask ids are uint256 on-chain
+    pub ask_id: String,
+}
 
-    common::response(
-        "Proof Generated",
-        StatusCode::OK,
-        Some(Value::String(url_hex)),
-    )
+#[post("/cancelProof")]
+async fn cancel_proof(_jsonbody: web::Json<CancelInput>, jobs: web::Data<Jobs>) -> impl Responder {
+    let Ok(ask_id) = U256::from_dec_str(&_jsonbody.ask_id) else {
+        return common::response("Invalid ask id", StatusCode::BAD_REQUEST, None);
+    };
+    // asks are proven by u64 id, so there is nothing to cancel for larger ones
+    if let Ok(ask_id) = u64::try_from(ask_id) {
+        jobs.cancel_ask(ask_id);
+    }
+    common::response("Proof request cancelled", StatusCode::OK, None)
 }
 
 #[derive(Serialize, Debug, Deserialize)]
-struct OnlyInput {
+struct JobInput {
+    // hex encoded prover data of the ask
     pub input: String,
+    pub ask_id: Option<u64>,
+    // picks the mode of the market when no mode is given
+    pub market_id: Option<String>,
+    pub mode: Option<ProofMode>,
 }
 
-#[post("/customBenchmark")]
-async fn generate_custom_benchmark(_jsonbody: web::Json<OnlyInput>) -> impl Responder {
-    let input_data = hex::decode(&_jsonbody.input).expect("Failed decoding inputs");
-    process_proof(input_data).await
+#[post("/jobs")]
+async fn create_job(
+    _jsonbody: web::Json<JobInput>,
+    jobs: web::Data<Jobs>,
+    modes: web::Data<ProofModeConfig>,
+) -> impl Responder {
+    let Ok(input) = hex::decode(&_jsonbody.input) else {
+        return common::response("Failed decoding inputs", StatusCode::BAD_REQUEST, None);
+    };
+    let mode = match (&_jsonbody.mode, &_jsonbody.market_id) {
+        (Some(mode), _) => *mode,
+        (None, Some(market_id)) => match U256::from_dec_str(market_id) {
+            Ok(market_id) => modes.mode(market_id),
+            Err(_) => return common::response("Invalid market id", StatusCode::BAD_REQUEST, None),
+        },
+        (None, None) => modes.default,
+    };
+    let request = JobRequest {
+        input,
+        mode,
+        ask_id: _jsonbody.ask_id,
+    };
+    match jobs.into_inner().submit(request) {
+        Some(id) => common::response(
+            "Job queued",
+            StatusCode::ACCEPTED,
+            Some(json!({ "job_id": id })),
+        ),
+        None => common::response("Proof request was cancelled", StatusCode::GONE, None),
+    }
 }
 
-use tokio::sync::Semaphore;
-use lazy_static::lazy_static;
-
-lazy_static! {
-    static ref SEMAPHORE: Semaphore = Semaphore::new(2);
+#[get("/jobs/{id}")]
+async fn job_status(id: web::Path<String>, jobs: web::Data<Jobs>) -> impl Responder {
+    match jobs.status(&id) {
+        Some(status) => common::response("Job status", StatusCode::OK, Some(json!(status))),
+        None => common::response("Job not found", StatusCode::NOT_FOUND, None),
+    }
 }
 
-#[post("/generateProof")]
-async fn generate_proof(_jsonbody: web::Json<common::GenerateProofInputs>) -> impl Responder {
-    // Acquire a permit from the semaphore.
-    let _permit = SEMAPHORE.acquire().await.unwrap();
+#[get("/jobs/{id}/result")]
+async fn job_result(id: web::Path<String>, jobs: web::Data<Jobs>) -> impl Responder {
+    job_response(&jobs, &id)
+}
 
-    let input_data = _jsonbody.ask.clone().prover_data.to_vec();
-    process_proof(input_data).await
+#[delete("/jobs/{id}")]
+async fn cancel_job(id: web::Path<String>, jobs: web::Data<Jobs>) -> impl Responder {
+    match jobs.cancel(&id) {
+        Some(status) if status.state == JobState::Cancelled => {
+            common::response("Job cancelled", StatusCode::OK, Some(json!(status)))
+        }
+        Some(status) if status.state == JobState::Cancelling => common::response(
+            "Job is cancelling, the proof in progress can't be interrupted and is dropped once it finishes",
+            StatusCode::ACCEPTED,
+            Some(json!(status)),
+        ),
+        Some(status) => common::response(
+            "Job is already finished",
+            StatusCode::CONFLICT,
+            Some(json!(status)),
+        ),
+        None => common::response("Job not found", StatusCode::NOT_FOUND, None),
+    }
 }
 
 pub fn routes(conf: &mut web::ServiceConfig) {
     let scope = web::scope("/api")
         .service(test)
+        .service(vkey)
         .service(benchmark)
         .service(generate_custom_benchmark)
-        .service(generate_proof);
+        .service(generate_proof)
+        .service(cancel_proof)
+        .service(create_job)
+        .service(job_status)
+        .service(job_result)
+        .service(cancel_job);
 
     conf.service(scope);
 }
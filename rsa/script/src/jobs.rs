@@ -0,0 +1,565 @@
+use crate::program::{self, Artifact, Prover};
+use crate::proof_mode::ProofMode;
+use crate::proof_store::{self, ProofStore};
+use actix_web::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{watch, Semaphore};
+use uuid::Uuid;
+
+const MAX_CONCURRENT_PROOFS_ENV: &str = "MAX_CONCURRENT_PROOFS";
+const DEFAULT_MAX_CONCURRENT_PROOFS: usize = 2;
+const JOB_RETENTION_ENV: &str = "JOB_RETENTION_SECS";
+const DEFAULT_JOB_RETENTION: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    // waiting for one of the MAX_CONCURRENT_PROOFS slots
+    Queued,
+    // running the program to count its instructions
+    Executing,
+    Proving,
+    // cancelled while executing or proving, the SP1 prover can't be interrupted so the job
+    // stays here until the proof finishes and is dropped
+    Cancelling,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Done | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+/// What `GET /jobs/{id}` reports.
+#[derive(Debug, Serialize, Clone)]
+pub struct JobStatus {
+    pub id: String,
+    pub ask_id: Option<u64>,
+    pub mode: ProofMode,
+    pub state: JobState,
+    // jobs queued ahead of this one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
+    // instructions the program ran, known once it is executed
+    pub cycles: Option<u64>,
+    // time taken to prove and verify, known once proven
+    pub proving_ms: Option<u64>,
+    // bytes of the saved proof, or of the encoded PLONK proof
+    pub proof_size: Option<u64>,
+    // unix timestamps in seconds
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Result of a finished proof, what the generator responds with.
+#[derive(Debug, Clone)]
+pub struct ProofOutput {
+    pub data: Value,
+    pub meta: Value,
+}
+
+pub struct JobRequest {
+    pub input: Vec<u8>,
+    pub mode: ProofMode,
+    pub ask_id: Option<u64>,
+}
+
+struct Job {
+    status: JobStatus,
+    // order of submission, the semaphore hands out slots in this order
+    seq: u64,
+    cancel_requested: bool,
+    // status code to respond with if the job failed
+    failure: StatusCode,
+    output: Option<ProofOutput>,
+    finished: Option<Instant>,
+    state: watch::Sender<JobState>,
+}
+
+/// Proof jobs, at most MAX_CONCURRENT_PROOFS (default 2) of them are proven at once. Finished
+/// jobs are kept for JOB_RETENTION_SECS (default an hour) so their results can be fetched.
+pub struct Jobs {
+    jobs: Mutex<HashMap<String, Job>>,
+    // asks the listener called off before their job was submitted, forgotten after the retention
+    cancelled_asks: Mutex<HashMap<u64, Instant>>,
+    permits: Semaphore,
+    next_seq: AtomicU64,
+    retention: Duration,
+    prover: Arc<dyn Prover>,
+    store: Arc<dyn ProofStore>,
+}
+
+impl Jobs {
+    pub fn new(prover: Arc<dyn Prover>, store: Arc<dyn ProofStore>) -> Result<Self, String> {
+        let max_concurrent_proofs = match std::env::var(MAX_CONCURRENT_PROOFS_ENV) {
+            Ok(value) => value
+                .parse::<usize>()
+                .ok()
+                .filter(|max| *max > 0)
+                .ok_or(format!("invalid {}: {}", MAX_CONCURRENT_PROOFS_ENV, value))?,
+            Err(_) => DEFAULT_MAX_CONCURRENT_PROOFS,
+        };
+        let retention = match std::env::var(JOB_RETENTION_ENV) {
+            Ok(value) => Duration::from_secs(
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid {}: {}", JOB_RETENTION_ENV, value))?,
+            ),
+            Err(_) => DEFAULT_JOB_RETENTION,
+        };
+        Ok(Self::with_limits(
+            prover,
+            store,
+            max_concurrent_proofs,
+            retention,
+        ))
+    }
+
+    pub fn with_limits(
+        prover: Arc<dyn Prover>,
+        store: Arc<dyn ProofStore>,
+        max_concurrent_proofs: usize,
+        retention: Duration,
+    ) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            cancelled_asks: Mutex::new(HashMap::new()),
+            permits: Semaphore::new(max_concurrent_proofs),
+            next_seq: AtomicU64::new(0),
+            retention,
+            prover,
+            store,
+        }
+    }
+
+    /// Queues the job and returns its id, `None` if its ask was called off already.
+    pub fn submit(self: &Arc<Self>, request: JobRequest) -> Option<String> {
+        self.prune();
+        if let Some(ask_id) = request.ask_id {
+            if self
+                .cancelled_asks
+                .lock()
+                .unwrap()
+                .remove(&ask_id)
+                .is_some()
+            {
+                return None;
+            }
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let (state, _) = watch::channel(JobState::Queued);
+        let job = Job {
+            status: JobStatus {
+                id: id.clone(),
+                ask_id: request.ask_id,
+                mode: request.mode,
+                state: JobState::Queued,
+                queue_position: None,
+                cycles: None,
+                proving_ms: None,
+                proof_size: None,
+                created_at: unix_now(),
+                started_at: None,
+                finished_at: None,
+                error: None,
+            },
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            cancel_requested: false,
+            failure: StatusCode::INTERNAL_SERVER_ERROR,
+            output: None,
+            finished: None,
+            state,
+        };
+        self.jobs.lock().unwrap().insert(id.clone(), job);
+        tokio::spawn(self.clone().run(id.clone(), request));
+        Some(id)
+    }
+
+    pub fn status(&self, id: &str) -> Option<JobStatus> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.get(id)?;
+        let mut status = job.status.clone();
+        if status.state == JobState::Queued {
+            status.queue_position = Some(
+                jobs.values()
+                    .filter(|other| other.status.state == JobState::Queued && other.seq < job.seq)
+                    .count(),
+            );
+        }
+        Some(status)
+    }
+
+    /// Cancels the job. Queued jobs never start. A running job is `Cancelling` until its
+    /// worker returns: it doesn't prove after executing, but a proof in progress can't be
+    /// interrupted, so its result is dropped when it finishes. `None` if the job is unknown.
+    pub fn cancel(&self, id: &str) -> Option<JobStatus> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(id)?;
+        if !job.status.state.is_finished() {
+            job.cancel_requested = true;
+            match job.status.state {
+                JobState::Queued => set_state(job, JobState::Cancelled),
+                JobState::Executing | JobState::Proving => set_state(job, JobState::Cancelling),
+                _ => {}
+            }
+        }
+        Some(job.status.clone())
+    }
+
+    /// Cancels the jobs of the ask, or the next one submitted for it if it has none yet.
+    pub fn cancel_ask(&self, ask_id: u64) {
+        self.prune();
+        let (running, finished): (Vec<_>, Vec<_>) = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|job| job.status.ask_id == Some(ask_id))
+            .map(|job| (job.status.id.clone(), job.status.state.is_finished()))
+            .partition(|(_, finished)| !finished);
+        // an ask that was proven already won't be submitted again
+        if running.is_empty() && finished.is_empty() {
+            self.cancelled_asks
+                .lock()
+                .unwrap()
+                .insert(ask_id, Instant::now());
+        }
+        for (id, _) in running {
+            self.cancel(&id);
+        }
+    }
+
+    /// Waits for the job to finish and returns its final status.
+    pub async fn wait(&self, id: &str) -> Option<JobStatus> {
+        let mut state = self.jobs.lock().unwrap().get(id)?.state.subscribe();
+        // the sender lives as long as the job, which outlives this wait unless it is pruned
+        let _ = state.wait_for(JobState::is_finished).await;
+        self.status(id)
+    }
+
+    /// The response of a finished job: the proof, or the reason there is none.
+    pub fn response_parts(&self, id: &str) -> Option<(StatusCode, String, Option<ProofOutput>)> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.get(id)?;
+        Some(match job.status.state {
+            JobState::Done => (
+                StatusCode::OK,
+                "Proof Generated".to_string(),
+                job.output.clone(),
+            ),
+            JobState::Cancelled => (
+                StatusCode::GONE,
+                "Proof request was cancelled".to_string(),
+                None,
+            ),
+            JobState::Failed => (
+                job.failure,
+                job.status.error.clone().unwrap_or_default(),
+                None,
+            ),
+            state => (
+                StatusCode::CONFLICT,
+                format!("Job is {}", json!(state).as_str().unwrap_or_default()),
+                None,
+            ),
+        })
+    }
+
+    async fn run(self: Arc<Self>, id: String, request: JobRequest) {
+        let _permit = self.permits.acquire().await.unwrap();
+        if !self.advance(&id, JobState::Executing) {
+            return;
+        }
+        let result = self.prove(&id, request).await;
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(&id) else {
+            return;
+        };
+        if job.cancel_requested {
+            if job.status.state != JobState::Cancelled {
+                set_state(job, JobState::Cancelled);
+            }
+            return;
+        }
+        match result {
+            Ok(output) => {
+                job.output = Some(output);
+                set_state(job, JobState::Done);
+            }
+            Err((failure, error)) => {
+                job.failure = failure;
+                job.status.error = Some(error);
+                set_state(job, JobState::Failed);
+            }
+        }
+    }
+
+    async fn prove(
+        &self,
+        id: &str,
+        request: JobRequest,
+    ) -> Result<ProofOutput, (StatusCode, String)> {
+        let internal = |err: String| (StatusCode::INTERNAL_SERVER_ERROR, err);
+        let stdin =
+            program::read_inputs(&request.input).map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+        let prover = self.prover.clone();
+        let execute_stdin = stdin.clone();
+        let cycles = tokio::task::spawn_blocking(move || prover.execute(execute_stdin))
+            .await
+            .map_err(|err| internal(err.to_string()))?
+            .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+        self.update(id, |job| job.status.cycles = Some(cycles));
+        if !self.advance(id, JobState::Proving) {
+            return Err(internal("cancelled".to_string()));
+        }
+
+        let prover = self.prover.clone();
+        let mode = request.mode;
+        let proving_started = Instant::now();
+        let artifact = tokio::task::spawn_blocking(move || prover.prove(mode, stdin))
+            .await
+            .map_err(|err| internal(err.to_string()))?
+            .map_err(internal)?;
+        let proving_ms = proving_started.elapsed().as_millis() as u64;
+        let proof_size = match &artifact {
+            Artifact::File(filename) => std::fs::metadata(filename).map(|file| file.len()).ok(),
+            Artifact::PlonkBn254(submission) => Some(submission.proof.len() as u64),
+        };
+        self.update(id, |job| {
+            job.status.proving_ms = Some(proving_ms);
+            job.status.proof_size = proof_size;
+        });
+
+        match artifact {
+            Artifact::File(filename) => {
+                let stored = if self.is_cancelled(id) {
+                    Err("cancelled".into())
+                } else {
+                    proof_store::store_file(self.store.as_ref(), Path::new(&filename)).await
+                };
+                let _ = std::fs::remove_file(&filename);
+                let stored = stored
+                    .map_err(|err| internal(format!("Failed to store the proof: {}", err)))?;
+                // the listener reads the hex encoded URL from data
+                Ok(ProofOutput {
+                    data: Value::String(hex::encode(&stored.url)),
+                    meta: json!({ "url": stored.url, "sha256": stored.sha256 }),
+                })
+            }
+            // the listener submits data as is
+            Artifact::PlonkBn254(submission) => Ok(ProofOutput {
+                data: Value::String(hex::encode(&submission.proof)),
+                meta: json!({
+                    "public_inputs": submission
+                        .public_inputs
+                        .iter()
+                        .map(|input| format!("0x{}", hex::encode(input)))
+                        .collect::<Vec<_>>(),
+                }),
+            }),
+        }
+    }
+
+    // Moves a running job to the next state, false if it was cancelled.
+    fn advance(&self, id: &str, state: JobState) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(id) else {
+            return false;
+        };
+        if job.cancel_requested {
+            set_state(job, JobState::Cancelled);
+            return false;
+        }
+        if state == JobState::Executing {
+            job.status.started_at = Some(unix_now());
+        }
+        set_state(job, state);
+        true
+    }
+
+    fn update(&self, id: &str, update: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            update(job);
+        }
+    }
+
+    fn is_cancelled(&self, id: &str) -> bool {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .map_or(true, |job| job.cancel_requested)
+    }
+
+    // Forgets jobs that finished and asks that were called off longer than the retention ago.
+    fn prune(&self) {
+        let retention = self.retention;
+        self.jobs.lock().unwrap().retain(|_, job| {
+            job.finished
+                .map_or(true, |finished| finished.elapsed() < retention)
+        });
+        self.cancelled_asks
+            .lock()
+            .unwrap()
+            .retain(|_, cancelled| cancelled.elapsed() < retention);
+    }
+}
+
+fn set_state(job: &mut Job, state: JobState) {
+    job.status.state = state;
+    if state.is_finished() {
+        job.status.finished_at = Some(unix_now());
+        job.finished = Some(Instant::now());
+    }
+    job.state.send_replace(state);
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proof_mode::PlonkSubmission;
+    use crate::proof_store::StoreError;
+    use async_trait::async_trait;
+    use ethers::abi::{encode, Token};
+    use sp1_sdk::SP1Stdin;
+
+    // Proves once it gets the gate, so proofs stay in `Proving` while a test holds it.
+    #[derive(Default)]
+    struct GatedProver {
+        gate: Mutex<()>,
+    }
+
+    impl Prover for GatedProver {
+        fn execute(&self, _stdin: SP1Stdin) -> Result<u64, String> {
+            Ok(1)
+        }
+
+        fn prove(&self, _mode: ProofMode, _stdin: SP1Stdin) -> Result<Artifact, String> {
+            let _gate = self.gate.lock().unwrap();
+            Ok(Artifact::PlonkBn254(PlonkSubmission {
+                proof: vec![1],
+                public_inputs: vec![],
+            }))
+        }
+    }
+
+    // PLONK proofs are never stored
+    struct NoStore;
+
+    #[async_trait]
+    impl ProofStore for NoStore {
+        async fn put(&self, _name: &str, _proof: &[u8]) -> Result<String, StoreError> {
+            Err("no store".into())
+        }
+    }
+
+    fn jobs(prover: &Arc<GatedProver>, retention: Duration) -> Arc<Jobs> {
+        Arc::new(Jobs::with_limits(
+            prover.clone(),
+            Arc::new(NoStore),
+            1,
+            retention,
+        ))
+    }
+
+    fn request(ask_id: Option<u64>) -> JobRequest {
+        let inputs = encode(&[Token::Bytes(vec![1])]);
+        JobRequest {
+            input: encode(&[Token::Uint(1.into()), Token::Bytes(inputs)]),
+            mode: ProofMode::PlonkBn254,
+            ask_id,
+        }
+    }
+
+    async fn reaches(jobs: &Jobs, id: &str, state: JobState) {
+        let mut states = jobs.jobs.lock().unwrap()[id].state.subscribe();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            states.wait_for(|current| *current == state),
+        )
+        .await
+        .expect("job state timed out")
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn queued_jobs_are_cancelled_before_they_start() {
+        let prover = Arc::<GatedProver>::default();
+        let jobs = jobs(&prover, DEFAULT_JOB_RETENTION);
+        let gate = prover.gate.lock().unwrap();
+        let running = jobs.submit(request(None)).unwrap();
+        reaches(&jobs, &running, JobState::Proving).await;
+        let queued = jobs.submit(request(None)).unwrap();
+        assert_eq!(jobs.status(&queued).unwrap().queue_position, Some(0));
+
+        assert_eq!(jobs.cancel(&queued).unwrap().state, JobState::Cancelled);
+        drop(gate);
+        assert_eq!(jobs.wait(&running).await.unwrap().state, JobState::Done);
+        let status = jobs.status(&queued).unwrap();
+        assert_eq!(status.state, JobState::Cancelled);
+        assert_eq!(status.started_at, None);
+        assert_eq!(jobs.response_parts(&queued).unwrap().0, StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn running_jobs_are_cancelling_until_the_proof_is_dropped() {
+        let prover = Arc::<GatedProver>::default();
+        let jobs = jobs(&prover, DEFAULT_JOB_RETENTION);
+        let gate = prover.gate.lock().unwrap();
+        let id = jobs.submit(request(None)).unwrap();
+        reaches(&jobs, &id, JobState::Proving).await;
+
+        assert_eq!(jobs.cancel(&id).unwrap().state, JobState::Cancelling);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(jobs.status(&id).unwrap().state, JobState::Cancelling);
+        assert_eq!(jobs.response_parts(&id).unwrap().0, StatusCode::CONFLICT);
+
+        drop(gate);
+        let status = jobs.wait(&id).await.unwrap();
+        assert_eq!(status.state, JobState::Cancelled);
+        // the proof finished, its result was dropped
+        assert!(status.proving_ms.is_some());
+        let (code, _, output) = jobs.response_parts(&id).unwrap();
+        assert_eq!(code, StatusCode::GONE);
+        assert!(output.is_none());
+    }
+
+    #[tokio::test]
+    async fn called_off_asks_are_forgotten_after_the_retention() {
+        let prover = Arc::<GatedProver>::default();
+        let jobs = jobs(&prover, Duration::from_millis(100));
+        jobs.cancel_ask(1);
+        jobs.cancel_ask(2);
+        assert!(jobs.submit(request(Some(1))).is_none());
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let id = jobs
+            .submit(request(Some(2)))
+            .expect("ask 2 is no longer called off");
+        assert_eq!(jobs.wait(&id).await.unwrap().state, JobState::Done);
+    }
+}
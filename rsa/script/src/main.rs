@@ -1,17 +1,49 @@
+mod benchmark;
 mod handler;
+mod jobs;
+mod program;
+mod proof_mode;
+mod proof_store;
 
-use actix_web::{App, HttpServer};
+use actix_web::{web, App, HttpServer};
+use jobs::Jobs;
+use program::Program;
+use proof_mode::ProofModeConfig;
+use proof_store::ProofStore;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let port: u16 = 3000;
+    sp1_sdk::utils::setup_logger();
 
-    let server = HttpServer::new(move || App::new().configure(handler::routes))
-        .client_request_timeout(Duration::new(0, 0))
-        .bind(("0.0.0.0", port))
-        .unwrap_or_else(|_| panic!("Can not bind to {}", &port))
-        .run();
+    // setting up the keys takes a while, it is done once and shared by all workers
+    let program = web::Data::new(Program::setup());
+    log::info!("Proving program with vkey hash {}", program.vkey_hash());
+    let store: Arc<dyn ProofStore> = proof_store::from_config_file()
+        .unwrap_or_else(|err| panic!("Can not set up the proof store: {}", err))
+        .into();
+    let jobs = web::Data::new(
+        Jobs::new(program.clone().into_inner(), store)
+            .unwrap_or_else(|err| panic!("Can not set up the proof jobs: {}", err)),
+    );
+    let modes = web::Data::new(
+        ProofModeConfig::from_config_file()
+            .unwrap_or_else(|err| panic!("Can not read the proof modes: {}", err)),
+    );
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(program.clone())
+            .app_data(jobs.clone())
+            .app_data(modes.clone())
+            .configure(handler::routes)
+    })
+    .client_request_timeout(Duration::new(0, 0))
+    .bind(("0.0.0.0", port))
+    .unwrap_or_else(|_| panic!("Can not bind to {}", &port))
+    .run();
 
     server.await
 }
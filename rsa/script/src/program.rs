@@ -0,0 +1,234 @@
+use crate::proof_mode::{PlonkSubmission, ProofMode};
+use ethers::abi::{decode, AbiType, Token};
+use ethers::types::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sp1_sdk::{
+    HashableKey, ProverClient, SP1ProofWithPublicValues, SP1ProvingKey, SP1Stdin, SP1VerifyingKey,
+};
+use std::error::Error;
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use uuid::Uuid;
+
+const KEYS_DIR_ENV: &str = "KEYS_DIR";
+const DEFAULT_KEYS_DIR: &str = "./keys";
+const PROVING_KEY_FILE: &str = "proving_key.bin";
+const VERIFYING_KEY_FILE: &str = "verifying_key.bin";
+const SETUP_MS_FILE: &str = "setup_ms.bin";
+
+/// The ELF we want to execute inside the zkVM.
+pub const ELF: &[u8] = include_bytes!("../../program/elf/riscv32im-succinct-zkvm-elf");
+
+/// The prover client and the keys of the ELF, set up once and shared by all workers.
+pub struct Program {
+    pub client: ProverClient,
+    pub pk: SP1ProvingKey,
+    pub vk: SP1VerifyingKey,
+    // time it took to set up the keys from scratch, saved and loaded with them. Unknown for keys
+    // saved without it
+    pub setup_ms: Option<u64>,
+}
+
+/// A verified proof of the program.
+pub enum Artifact {
+    // core and compressed proofs, saved to this file for the proof store
+    File(String),
+    PlonkBn254(PlonkSubmission),
+}
+
+/// What the jobs run their inputs through, the SP1 program outside of tests. Both calls block.
+pub trait Prover: Send + Sync {
+    /// Runs the program without proving it, returns the number of instructions it ran.
+    fn execute(&self, stdin: SP1Stdin) -> Result<u64, String>;
+
+    /// Proves and verifies the program.
+    fn prove(&self, mode: ProofMode, stdin: SP1Stdin) -> Result<Artifact, String>;
+}
+
+impl Program {
+    /// Loads the keys saved in `KEYS_DIR` (default `./keys`), or sets them up and saves them
+    /// there if they are missing or were set up for another ELF.
+    pub fn setup() -> Self {
+        let client = ProverClient::new();
+        let dir =
+            PathBuf::from(std::env::var(KEYS_DIR_ENV).unwrap_or(DEFAULT_KEYS_DIR.to_string()));
+        let (pk, vk, setup_ms) = match load_keys(&dir) {
+            Ok((pk, vk, setup_ms)) if pk.elf == ELF => {
+                log::info!("Loaded the proving keys from {}", dir.display());
+                (pk, vk, setup_ms)
+            }
+            _ => {
+                log::info!("Setting up the proving keys");
+                let started = Instant::now();
+                let (pk, vk) = client.setup(ELF);
+                let setup_ms = started.elapsed().as_millis() as u64;
+                if let Err(err) = save_keys(&dir, &pk, &vk, setup_ms) {
+                    log::warn!(
+                        "Failed to save the proving keys to {}: {}",
+                        dir.display(),
+                        err
+                    );
+                }
+                (pk, vk, Some(setup_ms))
+            }
+        };
+        Self {
+            client,
+            pk,
+            vk,
+            setup_ms,
+        }
+    }
+
+    /// Hash of the verifying key, committed to by every proof of the program.
+    pub fn vkey_hash(&self) -> String {
+        self.vk.bytes32()
+    }
+}
+
+impl Prover for Program {
+    fn execute(&self, stdin: SP1Stdin) -> Result<u64, String> {
+        let (_, report) = self
+            .client
+            .execute(ELF, stdin)
+            .map_err(|err| format!("execution failed: {}", err))?;
+        Ok(report.total_instruction_count())
+    }
+
+    fn prove(&self, mode: ProofMode, stdin: SP1Stdin) -> Result<Artifact, String> {
+        let Program { client, pk, vk, .. } = self;
+        match mode {
+            ProofMode::Core => {
+                let proof = client
+                    .prove(pk, stdin)
+                    .map_err(|err| format!("proving failed: {}", err))?;
+                client
+                    .verify(&proof, vk)
+                    .map_err(|err| format!("verification failed: {}", err))?;
+                save_proof(&proof, |proof| client.verify(proof, vk).is_ok())
+            }
+            ProofMode::Compressed => {
+                let proof = client
+                    .prove_compressed(pk, stdin)
+                    .map_err(|err| format!("proving failed: {}", err))?;
+                client
+                    .verify_compressed(&proof, vk)
+                    .map_err(|err| format!("verification failed: {}", err))?;
+                save_proof(&proof, |proof| client.verify_compressed(proof, vk).is_ok())
+            }
+            ProofMode::PlonkBn254 => {
+                let proof = client
+                    .prove_plonk(pk, stdin)
+                    .map_err(|err| format!("proving failed: {}", err))?;
+                client
+                    .verify_plonk(&proof, vk)
+                    .map_err(|err| format!("verification failed: {}", err))?;
+                let submission = PlonkSubmission::new(&proof)
+                    .map_err(|err| format!("failed to encode the proof: {}", err))?;
+                Ok(Artifact::PlonkBn254(submission))
+            }
+        }
+    }
+}
+
+/// Reads the program's inputs from `abi.encode(uint256 count, bytes abi.encode(bytes[count]))`.
+pub fn read_inputs(input_data: &[u8]) -> Result<SP1Stdin, String> {
+    let outer_types = vec![
+        <ethers::types::U256 as AbiType>::param_type(), // uint256
+        <Bytes as AbiType>::param_type(),               // bytes (nested)
+    ];
+    let outer_decoded: Vec<Token> = decode(&outer_types, input_data)
+        .map_err(|err| format!("Decoding outer layer failed: {}", err))?;
+
+    let num_bytes = outer_decoded[0]
+        .clone()
+        .into_uint()
+        .ok_or("Failed to decode U256")?;
+    if num_bytes.is_zero() || num_bytes > usize::MAX.into() {
+        return Err("Invalid number of byte inputs".to_string());
+    }
+    let num_bytes_usize = num_bytes.as_usize();
+
+    let nested_data: Vec<u8> = outer_decoded[1]
+        .clone()
+        .into_bytes()
+        .ok_or("Failed to decode nested bytes")?;
+
+    // Now, decode the nested bytes array
+    let nested_types = vec![<Bytes as AbiType>::param_type(); num_bytes_usize];
+    let nested_decoded: Vec<Token> = decode(&nested_types, &nested_data)
+        .map_err(|err| format!("Decoding nested bytes array failed: {}", err))?;
+    if num_bytes_usize != nested_decoded.len() {
+        return Err("Invalid number of byte inputs".to_string());
+    }
+
+    // Create a new stdin with the input for the program.
+    let mut stdin = SP1Stdin::new();
+    for token in nested_decoded {
+        let input: Vec<u8> = token.into_bytes().ok_or("Failed to decode Vec<u8>")?;
+        stdin.write(&input);
+    }
+    Ok(stdin)
+}
+
+// Saves the proof to a file named by uuid and checks it still verifies once loaded back.
+fn save_proof<P>(
+    proof: &SP1ProofWithPublicValues<P>,
+    verify: impl FnOnce(&SP1ProofWithPublicValues<P>) -> bool,
+) -> Result<Artifact, String>
+where
+    P: Serialize + DeserializeOwned + Debug + Clone,
+{
+    let filename = format!("proof-{}.bin", Uuid::new_v4());
+    proof
+        .save(&filename)
+        .map_err(|err| format!("saving proof failed: {}", err))?;
+
+    let verified = SP1ProofWithPublicValues::<P>::load(&filename)
+        .map(|deserialized_proof| verify(&deserialized_proof))
+        .unwrap_or(false);
+    if !verified {
+        let _ = fs::remove_file(&filename);
+        return Err("Saved proof failed verification".to_string());
+    }
+    Ok(Artifact::File(filename))
+}
+
+type Keys = (SP1ProvingKey, SP1VerifyingKey, Option<u64>);
+
+fn load_keys(dir: &Path) -> Result<Keys, Box<dyn Error>> {
+    let pk = bincode::deserialize_from(File::open(dir.join(PROVING_KEY_FILE))?)?;
+    let vk = bincode::deserialize_from(File::open(dir.join(VERIFYING_KEY_FILE))?)?;
+    // keys saved without their setup time are still used
+    let setup_ms = File::open(dir.join(SETUP_MS_FILE))
+        .ok()
+        .and_then(|file| bincode::deserialize_from(file).ok());
+    Ok((pk, vk, setup_ms))
+}
+
+fn save_keys(
+    dir: &Path,
+    pk: &SP1ProvingKey,
+    vk: &SP1VerifyingKey,
+    setup_ms: u64,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    save_atomically(&dir.join(PROVING_KEY_FILE), pk)?;
+    save_atomically(&dir.join(VERIFYING_KEY_FILE), vk)?;
+    save_atomically(&dir.join(SETUP_MS_FILE), &setup_ms)?;
+    Ok(())
+}
+
+// Writes next to the file and renames it over, so a crash mid-write can't leave a truncated key
+fn save_atomically<T: Serialize>(path: &Path, value: &T) -> Result<(), Box<dyn Error>> {
+    let tmp = path.with_extension("tmp");
+    let mut file = BufWriter::new(File::create(&tmp)?);
+    bincode::serialize_into(&mut file, value)?;
+    file.into_inner()?.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use ethers::abi::{encode, Token};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+use sp1_sdk::SP1PlonkBn254Proof;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+const CONFIG_PATH_ENV: &str = "PROOF_MODE_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "./proof_mode.json";
+
+/// Kind of SP1 proof generated for an ask.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofMode {
+    // one STARK per shard, only verifiable off-chain
+    #[default]
+    Core,
+    // shards recursively compressed into a single STARK, only verifiable off-chain
+    Compressed,
+    // compressed proof wrapped in PLONK over BN254, verifiable by the market's verifier contract
+    PlonkBn254,
+}
+
+/// Proof mode per market, read from the JSON file at `PROOF_MODE_CONFIG`
+/// (default `./proof_mode.json`).
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ProofModeConfig {
+    // mode of markets that aren't listed
+    pub default: ProofMode,
+    // by market id
+    pub markets: HashMap<String, ProofMode>,
+}
+
+impl ProofModeConfig {
+    pub fn from_config_file() -> Result<Self, Box<dyn Error>> {
+        let path = std::env::var(CONFIG_PATH_ENV).unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                let config: Self = serde_json::from_str(&content)?;
+                for market_id in config.markets.keys() {
+                    U256::from_dec_str(market_id)
+                        .map_err(|_| format!("invalid market id {} in {}", market_id, path))?;
+                }
+                Ok(config)
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                log::info!("No proof mode config at {}, generating core proofs", path);
+                Ok(Self::default())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn mode(&self, market_id: U256) -> ProofMode {
+        self.markets
+            .get(&market_id.to_string())
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// A PLONK proof ready to be submitted for an ask.
+pub struct PlonkSubmission {
+    // `abi.encode(bytes proof)`, what `encodeProof` of the plonk verifier wrapper returns
+    pub proof: Vec<u8>,
+    // public inputs the verifier checks the proof against: the program's vkey hash and the
+    // digest of its committed values
+    pub public_inputs: Vec<[u8; 32]>,
+}
+
+impl PlonkSubmission {
+    pub fn new(proof: &SP1PlonkBn254Proof) -> Result<Self, Box<dyn Error>> {
+        let encoded_proof = hex::decode(&proof.proof.encoded_proof)?;
+        let mut public_inputs = vec![];
+        for input in &proof.proof.public_inputs {
+            let mut word = [0u8; 32];
+            U256::from_dec_str(input)?.to_big_endian(&mut word);
+            public_inputs.push(word);
+        }
+        Ok(Self {
+            proof: encode(&[Token::Bytes(encoded_proof)]),
+            public_inputs,
+        })
+    }
+}